@@ -3,4 +3,5 @@
 set -eu
 
 cargo +stable contract build --manifest-path erc20/Cargo.toml
+cargo +stable contract build --manifest-path migration/Cargo.toml
 cargo +stable contract build
\ No newline at end of file
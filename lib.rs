@@ -1,5 +1,16 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+mod math;
+mod psp22;
+
+pub use self::meta_defender::{
+    MetaDefender,
+    MetaDefenderRef,
+    Error as MetaDefenderError,
+    Role,
+    Tranche,
+    STORAGE_VERSION,
+};
 
 
 #[ink::contract]
@@ -7,11 +18,135 @@ mod meta_defender {
 
     use ink::prelude::vec::Vec;
     use ink::storage::Mapping;
+    use ink::storage::traits::StorageKey;
    
-    use erc20::{Erc20, Erc20Ref , Erc20Error};
+    use erc20::{Erc20Ref , Erc20Error};
     use ink::env::call::FromAccountId;
+    use crate::math::{self, MathError, Rate, Ray, Rounding};
+    use crate::psp22::{PSP22Error, PSP22};
+    use ink::prelude::format;
+
+    /// The storage layout version written by this code.
+    ///
+    /// Bump it together with a new arm in the `migrate_*` hooks whenever a released
+    /// encoding of `ProviderInfo`, `PolicyInfo` or `HistoricalProviderInfo` changes,
+    /// the arms translate straight to the current layout. A change of the packed fields
+    /// of the root needs a `migrate_root` and a `migration` contract like the ones from version 1.
+    pub const STORAGE_VERSION: u32 = 2;
+
+    /// The most policies a single call walks through, to keep its weight bounded.
+    pub const MAX_SWEEP: u32 = 50;
+
+    /// How long a beneficiary has to appeal a refused claim, in milliseconds.
+    pub const APPEAL_WINDOW: Timestamp = 7 * 86_400_000;
+
+    /// How long the judger has to decide a claim before the beneficiary may escalate it to
+    /// the officials, in milliseconds.
+    pub const CLAIM_DEADLINE: Timestamp = 14 * 86_400_000;
+
+    /// How long underwriters have to object to an accepted large claim before it is paid,
+    /// in milliseconds.
+    pub const CHALLENGE_PERIOD: Timestamp = 3 * 86_400_000;
+
+    /// How long a withdrawal request can be executed once it is ready, in milliseconds.
+    pub const WITHDRAWAL_WINDOW: Timestamp = 2 * 86_400_000;
+
+    /// The tranches underwriting capital is provided to.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum Tranche {
+        /// Takes the losses the risk reserve can't pay first, for the larger share of the rewards.
+        #[default]
+        Junior,
+        /// Only takes losses once the junior capital is exhausted. Its stoken are not the PSP22 token
+        /// and can't be transferred.
+        Senior,
+    }
+
+    /// The capital of a tranche and the accumulators of its stoken.
+    #[derive(scale::Encode, scale::Decode, Debug, Clone)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    struct TranchePool {
+        /// The capital behind one stoken, scaled by `Rate::ONE`.
+        exchange_rate: Balance,
+        acc_rps: Balance,
+        acc_sps: Balance,
+        acc_sps_down: Balance,
+        stoken_supply: Balance,
+        token_staked: Balance,
+        token_frozen: Balance,
+        /// How many times the tranche started over after a loss wiped it out.
+        restarts: u32,
+    }
+
+    impl TranchePool {
+        fn new() -> Self {
+            TranchePool {
+                exchange_rate: Rate::ONE,
+                acc_rps: 0,
+                acc_sps: 0,
+                acc_sps_down: 0,
+                stoken_supply: 0,
+                token_staked: 0,
+                token_frozen: 0,
+                restarts: 0,
+            }
+        }
+
+        /// The stoken `capital` buys, a wiped out tranche takes no capital until its last stoken is burned.
+        fn stoken_for(&self, capital: Balance) -> Result<Balance> {
+            if self.exchange_rate == 0 {
+                return Err(Error::WipedOutTranche);
+            }
+            Ok(Rate(self.exchange_rate).div(capital, Rounding::Down)?)
+        }
+
+        /// Adds `stoken_amount` of new stoken backed by `capital`.
+        fn mint(&mut self, stoken_amount: Balance, capital: Balance) -> Result<()> {
+            self.stoken_supply = add(self.stoken_supply, stoken_amount)?;
+            self.token_staked = add(self.token_staked, capital)?;
+            Ok(())
+        }
+
+        /// Removes `stoken_amount` of stoken and the `capital` behind them.
+        ///
+        /// A wiped out tranche starts over at `Rate::ONE` once its last stoken is burned. The
+        /// accumulators are kept: new positions take their debts from them, and the policies
+        /// still frozen fold their share into `acc_sps_down` later.
+        fn burn(&mut self, stoken_amount: Balance, capital: Balance) -> Result<()> {
+            self.stoken_supply = sub(self.stoken_supply, stoken_amount)?;
+            self.token_staked = sub(self.token_staked, capital)?;
+            if self.stoken_supply == 0 && self.exchange_rate == 0 {
+                self.exchange_rate = Rate::ONE;
+                self.restarts = self.restarts.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+            }
+            Ok(())
+        }
+
+        /// Writes `loss` off the staked and frozen capital, lowering the exchange rate in proportion.
+        fn absorb(&mut self, loss: Balance) -> Result<()> {
+            if loss == 0 {
+                return Ok(());
+            }
+            let pre_reserve = add(self.token_staked, self.token_frozen)?;
+            let after_reserve = sub(pre_reserve, loss)?;
+
+            let delta_rate = Rate::from_ratio(after_reserve, pre_reserve, Rounding::Down)?;
 
+            self.exchange_rate = delta_rate.mul(self.exchange_rate, Rounding::Down)?;
 
+            self.token_staked = delta_rate.mul(self.token_staked, Rounding::Down)?;
+
+            self.token_frozen = delta_rate.mul(self.token_frozen, Rounding::Down)?;
+            Ok(())
+        }
+    }
 
     #[derive(scale::Encode, scale::Decode, Debug)]
     #[cfg_attr(
@@ -25,6 +160,9 @@ mod meta_defender {
         stoken_amount: Balance,
         rdebt:Balance,
         sdebt:Balance,
+        /// Whether anyone may compound the reward of the position with `compound_rewards_batch`.
+        auto_compound: bool,
+        tranche: Tranche,
     }
 
     #[derive(scale::Encode, scale::Decode, Debug)]
@@ -32,8 +170,40 @@ mod meta_defender {
         feature = "std",
         derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
     )]
-    #[derive(Default)]
+    #[derive(Default, Clone)]
     struct PolicyInfo {
+        id: u128,
+        beneficiary: AccountId,
+        /// The account which paid the cover and gets the deposit back.
+        payer: AccountId,
+        coverage: Balance,
+        deposit: Balance,
+        start_time: Timestamp,
+        effective_until: Timestamp,
+        /// The term of the policy in days.
+        duration: u32,
+        latest_provider_index: u128,
+        /// The coverage locked per junior stoken.
+        delta_acc_sps: Balance,
+        /// The coverage locked per senior stoken.
+        senior_delta_acc_sps: Balance,
+        /// The coverage paid out so far, the policy is claimed once it reaches `coverage`.
+        claimed_amount: Balance,
+        is_claimed: bool,
+        in_claim_applying: bool,
+        is_canceled: bool,
+    }
+
+    impl PolicyInfo {
+        /// The coverage not paid out yet.
+        fn remaining_coverage(&self) -> Result<Balance> {
+            sub(self.coverage, self.claimed_amount)
+        }
+    }
+
+    /// The layout of `PolicyInfo` up to storage version 1, before policies had a duration and a payer.
+    #[derive(scale::Encode, scale::Decode)]
+    struct PolicyInfoV1 {
         id: u128,
         beneficiary: AccountId,
         coverage: Balance,
@@ -47,6 +217,54 @@ mod meta_defender {
         is_canceled: bool,
     }
 
+    impl From<PolicyInfoV1> for PolicyInfo {
+        /// Every cover had a term of 90 days and was paid by its beneficiary before version 2.
+        fn from(old: PolicyInfoV1) -> Self {
+            PolicyInfo {
+                id: old.id,
+                beneficiary: old.beneficiary,
+                payer: old.beneficiary,
+                coverage: old.coverage,
+                deposit: old.deposit,
+                start_time: old.start_time,
+                effective_until: old.effective_until,
+                duration: 90,
+                latest_provider_index: old.latest_provider_index,
+                delta_acc_sps: old.delta_acc_sps,
+                senior_delta_acc_sps: 0,
+                claimed_amount: if old.is_claimed { old.coverage } else { 0 },
+                is_claimed: old.is_claimed,
+                in_claim_applying: old.in_claim_applying,
+                is_canceled: old.is_canceled,
+            }
+        }
+    }
+
+    /// The layout of `ProviderInfo` up to storage version 1, before rewards could be compounded
+    /// and capital was split into tranches.
+    #[derive(scale::Encode, scale::Decode)]
+    struct ProviderInfoV1 {
+        index: u128,
+        participation_time: Timestamp,
+        stoken_amount: Balance,
+        rdebt: Balance,
+        sdebt: Balance,
+    }
+
+    impl From<ProviderInfoV1> for ProviderInfo {
+        fn from(old: ProviderInfoV1) -> Self {
+            ProviderInfo {
+                index: old.index,
+                participation_time: old.participation_time,
+                stoken_amount: old.stoken_amount,
+                rdebt: old.rdebt,
+                sdebt: old.sdebt,
+                auto_compound: false,
+                tranche: Tranche::Junior,
+            }
+        }
+    }
+
 
     #[derive(scale::Encode, scale::Decode, Debug)]
     #[cfg_attr(
@@ -60,11 +278,182 @@ mod meta_defender {
         ftoken: Balance,
         acc_sps_while_left: Balance, 
         sdebt_before: Balance, 
+        tranche: Tranche,
+        /// The `restarts` of the tranche when the capital was frozen, it is worthless after another one.
+        restarts: u32,
+    }
+
+    /// The layout of `HistoricalProviderInfo` up to storage version 1, before capital was split into tranches.
+    #[derive(scale::Encode, scale::Decode)]
+    struct HistoricalProviderInfoV1 {
+        index_before: u128,
+        stoken_amount_before: Balance,
+        ftoken: Balance,
+        acc_sps_while_left: Balance,
+        sdebt_before: Balance,
+    }
+
+    impl From<HistoricalProviderInfoV1> for HistoricalProviderInfo {
+        fn from(old: HistoricalProviderInfoV1) -> Self {
+            HistoricalProviderInfo {
+                index_before: old.index_before,
+                stoken_amount_before: old.stoken_amount_before,
+                ftoken: old.ftoken,
+                acc_sps_while_left: old.acc_sps_while_left,
+                sdebt_before: old.sdebt_before,
+                tranche: Tranche::Junior,
+                restarts: 0,
+            }
+        }
+    }
+
+    /// The packed fields of the root of the contract at storage version 1, in order. The mappings
+    /// keep their keys across versions and are not part of it.
+    #[derive(scale::Encode, scale::Decode)]
+    struct RootV1 {
+        provider_count: u128,
+        exchange_rate: Balance,
+        policy_count: u128,
+        acc_rps: Balance,
+        acc_sps: Balance,
+        acc_sps_down: Balance,
+        token_staked_here: Balance,
+        stoken_supply: Balance,
+        token_frozen_here: Balance,
+        total_coverage: Balance,
+        k_last: u128,
+        latest_unfrozen_index: u128,
+        initial_fee: u128,
+        min_fee: u128,
+        claimable_team_reward: Balance,
+        virtual_param: Balance,
+        locked: bool,
+        storage_version: u32,
+        erc20: Erc20Ref,
+        risk_reserve: AccountId,
+    }
+
+    /// The groups of messages which can be paused separately.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(::scale_info::TypeInfo))]
+    pub enum PauseFlag {
+        /// `provide_capital`, `increase_capital`, `compound_rewards` and `compound_rewards_batch`.
+        Underwriting,
+        /// `buy_cover`.
+        NewCovers,
+        /// `accept_apply`, approving votes of `vote_claim` and `execute_claim`.
+        ClaimsPayout,
+        /// Every message moving capital or rewards out of the pool: `provider_take_reward`,
+        /// `provider_abolish`, `decrease_capital`, `historical_provider_withdraw` and `unused_capital_for_mining`,
+        /// and the PSP22 `transfer` and `transfer_from` of stoken.
+        Withdrawals,
+    }
+
+    /// The price of a cover, as charged by `buy_cover`.
+    #[derive(scale::Encode, scale::Decode, Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "std", derive(::scale_info::TypeInfo))]
+    pub struct Quote {
+        /// The premium, `get_fee` scaled by the multiplier of the term.
+        pub cover_fee: Balance,
+        /// Refunded when the policy is cancelled.
+        pub deposit: Balance,
+        pub total: Balance,
+    }
+
+    /// Where a claim stands in its review.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub enum ClaimStatus {
+        /// Waiting for the judger.
+        Pending,
+        /// Refused, and may be appealed once within `APPEAL_WINDOW`.
+        Refused,
+        /// Refused and appealed, waiting for the judger again.
+        Appealed,
+        /// Undecided past its deadline or vetoed by the underwriters, waiting for an official.
+        Escalated,
+        /// Accepted for more than the large claim share of the capital, waiting out `CHALLENGE_PERIOD`.
+        Challenged,
+        Accepted,
+    }
+
+    /// The latest claim on a policy.
+    #[derive(scale::Encode, scale::Decode, Debug, Clone, PartialEq, Eq)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct Claim {
+        pub policy_id: u128,
+        /// The hash of the evidence the beneficiary handed in off-chain.
+        pub evidence: Hash,
+        /// The amount asked for, at most what is left of the coverage.
+        pub amount: Balance,
+        pub submitted_at: Timestamp,
+        pub status: ClaimStatus,
+        /// The code the judger gave when refusing, 0 otherwise.
+        pub reason: u32,
+        pub decided_at: Option<Timestamp>,
+        /// After this the claim may be escalated if it is still undecided.
+        pub deadline: Timestamp,
+        pub appealed: bool,
+        /// Counts the reviews of the policy's claims, votes are only valid in the round they were cast.
+        pub round: u32,
+        /// The votes cast for the claim in this round.
+        pub approvals: u32,
+        pub rejections: u32,
+        /// The smallest amount the committee approved in this round,
+        /// and the amount a challenged claim pays unless the underwriters veto it.
+        pub approved_amount: Balance,
+        pub challenge_ends: Option<Timestamp>,
+        /// The stoken of the underwriters objecting in this round.
+        pub objections: Balance,
+    }
+
+    impl Claim {
+        /// Puts the claim before a new review, the votes cast so far no longer count.
+        fn start_round(&mut self) -> Result<()> {
+            self.round = self.round.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+            self.approvals = 0;
+            self.rejections = 0;
+            self.objections = 0;
+            Ok(())
+        }
+
+        /// Checks that the refused claim may still be appealed at `today`.
+        fn ensure_appealable(&self, today: Timestamp) -> Result<()> {
+            if self.appealed {
+                return Err(Error::AlreadyAppealed);
+            }
+            let decided_at = self.decided_at.unwrap_or_default();
+            if today > decided_at.checked_add(APPEAL_WINDOW).ok_or(Error::ArithmeticOverflow)? {
+                return Err(Error::AppealWindowClosed);
+            }
+            Ok(())
+        }
+    }
+
+    /// A role transfer waiting for the proposed account to accept it.
+    #[derive(scale::Encode, scale::Decode, Debug, Clone, PartialEq, Eq)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    pub struct PendingRole {
+        from: AccountId,
+        to: AccountId,
+        expires_at: Option<Timestamp>,
     }
 
     // The Meta_Defender result types.
     pub type Result<T> = core::result::Result<T, Error>;
 
+    /// A policy id, the round of its claim and an account.
+    type ClaimRoundKey = (u128, u32, AccountId);
+
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(::scale_info::TypeInfo))]
     pub enum Error {
@@ -74,799 +463,6204 @@ mod meta_defender {
         ExistingUnderWriter,
         NotUnderwriter,
         NotValidUnderwriter,
-        ProviderLeavingInProgress,
         NotHistoricalUnderwriter,
-        HistoricalProviderLeavingInProgress,
         InsufficientSToken,
         NotExistedPolicy,
         AlreadyCancelledPolicy,
         NotExpiredPolicy,
         ClaimingInProgress,
         OnlyPolicyHolderCanCancel,
-        PreviousPolicyNotCancelled,
         NotBeneficiary,
         AlreadyClaimedPolicy,
         InClaimingProgress,
         NotEffectivePolicy,
         NotInClaimingProgress,
         NotValidMiningProxy,
+        HistoricalCapitalNotWithdrawn,
+        /// Returned if a computation over- or underflows.
+        ArithmeticOverflow,
+        /// Returned if a computation divides by zero, e.g. while there is no stoken supply.
+        DivisionByZero,
+        /// Returned if a message calling into the token is entered again before it returned.
+        ReentrantCall,
+        NotGuardian,
+        NotPauseAdmin,
+        NotProxyManager,
+        /// Returned if the last official would be revoked.
+        LastOfficial,
+        NoPendingRole,
+        /// Returned if the caller is not the proposed account of a pending role.
+        NotPendingAccount,
+        PendingRoleExpired,
+        /// Returned if the message is paused.
+        Paused,
+        UpgradeFailed,
+        /// Returned if no migration hook translates from the stored layout version.
+        UnknownStorageVersion,
+        /// Returned if a stored entry doesn't have the layout being migrated from.
+        MigrationFailed,
+        /// Returned if the state changes before `finish_migration` is called.
+        MigrationInProgress,
+        /// Returned if the duration of a cover is not a whitelisted term.
+        UnsupportedDuration,
+        InvalidPolicyTerm,
+        /// Returned if a cover costs more than the caller accepted.
+        SlippageExceeded,
+        /// Returned if a cover is bought after the caller's deadline.
+        Expired,
+        /// Returned if the caller did not pay for the policy.
+        NotPayer,
+        /// Returned if the keeper share is above 100%.
+        InvalidKeeperShare,
+        /// Returned if a batch has more than MAX_SWEEP entries.
+        BatchTooLarge,
+        /// Returned if a claim asks for nothing or for more than the coverage.
+        InvalidClaimAmount,
+        /// Returned if the claim was not refused.
+        NotRefusedClaim,
+        /// Returned if a refused claim was already appealed once.
+        AlreadyAppealed,
+        /// Returned if the appeal window of a refused claim is over.
+        AppealWindowClosed,
+        /// Returned if the claim is escalated already.
+        AlreadyEscalated,
+        /// Returned if a claim is escalated before its deadline.
+        ClaimDeadlineNotReached,
+        /// Returned if the account already voted on the claim in this round.
+        AlreadyVoted,
+        /// Returned if the vote threshold is zero or above the number of judgers,
+        /// or if a judger leaving would bring them below a threshold above one.
+        InvalidThreshold,
+        /// Returned if a claim is decided alone while it needs the votes of the committee.
+        CommitteeVoteRequired,
+        /// Returned if a share of the challenge parameters is above 100% or the veto share is zero.
+        InvalidChallengeParams,
+        /// Returned if the claim waits out its challenge period.
+        ClaimChallenged,
+        /// Returned if the claim is not in a challenge period.
+        NotChallengedClaim,
+        /// Returned if an objection comes after the challenge period.
+        ChallengePeriodOver,
+        /// Returned if a challenged claim is paid before its challenge period is over.
+        ChallengePeriodNotOver,
+        /// Returned if the underwriter already objected to the claim in this round.
+        AlreadyObjected,
+        /// Returned if a partial withdrawal is zero or the whole position, which is `provider_abolish`.
+        InvalidStokenAmount,
+        /// Returned if an underwriter exits within the lock period or before the cooldown of their request.
+        CapitalLocked,
+        /// Returned if an underwriter exits without a withdrawal request, or after it expired.
+        NoWithdrawalRequest,
+        /// Returned if stoken are sent to a position in the other tranche.
+        TrancheMismatch,
+        /// Returned if stoken which objected to a claim leave their account before its challenge period is over.
+        StokenInObjection,
+        /// Returned if the senior reward weight is above 100%.
+        InvalidRewardWeight,
+        /// Returned if capital goes into a wiped out tranche before its last stoken are burned.
+        WipedOutTranche,
         InsufficientBalance,
         InsufficientAllowance,
         TransferError,
     }
 
+    impl From<Erc20Error> for Error {
+        fn from(e: Erc20Error) -> Self {
+            match e {
+                Erc20Error::InsufficientAllowance => Error::InsufficientAllowance,
+                Erc20Error::InsufficientBalance => Error::InsufficientBalance,
+            }
+        }
+    }
+
+    impl From<Error> for PSP22Error {
+        fn from(e: Error) -> Self {
+            match e {
+                Error::InsufficientBalance => PSP22Error::InsufficientBalance,
+                Error::InsufficientAllowance => PSP22Error::InsufficientAllowance,
+                e => PSP22Error::Custom(format!("{:?}", e)),
+            }
+        }
+    }
+
+    impl From<MathError> for Error {
+        fn from(e: MathError) -> Self {
+            match e {
+                MathError::Overflow => Error::ArithmeticOverflow,
+                MathError::DivisionByZero => Error::DivisionByZero,
+            }
+        }
+    }
+
+    /// Returns `a + b`.
+    fn add(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_add(b).ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Returns `a - b`, failing if `b` is larger than `a`.
+    fn sub(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_sub(b).ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Returns `a * b`.
+    fn mul(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_mul(b).ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Returns `a / b` rounded down.
+    fn div(a: Balance, b: Balance) -> Result<Balance> {
+        a.checked_div(b).ok_or(Error::DivisionByZero)
+    }
+
+    /// Returns when a policy of `duration` days starting at `start` ends.
+    fn term_end(start: Timestamp, duration: u32) -> Result<Timestamp> {
+        Timestamp::from(duration)
+            .checked_mul(86_400_000)
+            .and_then(|term| start.checked_add(term))
+            .ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Returns whether anyone may cancel `policy`: it expired more than a day ago and has no claim.
+    fn keeper_cancellable(policy: &PolicyInfo, today: Timestamp) -> bool {
+        !policy.is_canceled && !policy.in_claim_applying && today.saturating_sub(policy.effective_until) > 86_400_000
+    }
+
+    /// Returns `a * b / c` rounded down.
+    fn mul_div(a: Balance, b: Balance, c: Balance) -> Result<Balance> {
+        Ok(math::mul_div(a, b, c, Rounding::Down)?)
+    }
+
+    /// Returns the part of `position` made of `stoken_amount` of its stoken, with its share of
+    /// `rdebt` and `sdebt` and thereby of the pending reward and shadow. The part doesn't compound.
+    fn split_position(position: &ProviderInfo, stoken_amount: Balance) -> Result<ProviderInfo> {
+        Ok(ProviderInfo {
+            index: position.index,
+            participation_time: position.participation_time,
+            stoken_amount,
+            rdebt: mul_div(position.rdebt, stoken_amount, position.stoken_amount)?,
+            sdebt: mul_div(position.sdebt, stoken_amount, position.stoken_amount)?,
+            auto_compound: false,
+            tranche: position.tranche,
+        })
+    }
+
+    /// Splits `amount` between the junior and the senior tranche in proportion to their weights.
+    ///
+    /// The junior tranche takes the rounding, and everything if both weights are zero.
+    fn split_between(amount: Balance, junior_weight: Balance, senior_weight: Balance) -> Result<(Balance, Balance)> {
+        let total_weight = add(junior_weight, senior_weight)?;
+        if total_weight == 0 {
+            return Ok((amount, 0));
+        }
+        let senior = mul_div(amount, senior_weight, total_weight)?;
+        Ok((sub(amount, senior)?, senior))
+    }
+
+    /// Returns `amount` per stoken of a tranche of `supply` stoken, zero if there is nothing to share.
+    fn per_stoken(amount: Balance, supply: Balance, rounding: Rounding) -> Result<Balance> {
+        if amount == 0 {
+            return Ok(0);
+        }
+        Ok(Ray::per_share(amount, supply, rounding)?.0)
+    }
+
+    /// The privileged roles of the Meta_Defender.
+    ///
+    /// An account may hold several roles and a role may be held by several accounts.
+    /// Officials grant and revoke every role.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(::scale_info::TypeInfo))]
+    pub enum Role {
+        /// Accepts and refuses claims and sends unused capital to mining proxies.
+        Judger,
+        /// Administers the roles and claims the team reward.
+        Official,
+        Guardian,
+        PauseAdmin,
+        /// Adds and terminates mining proxies.
+        ProxyManager,
+    }
+
+    impl Role {
+        /// The error returned when the caller lacks this role.
+        fn missing(self) -> Error {
+            match self {
+                Role::Judger => Error::NotJudger,
+                Role::Official => Error::NotOfficial,
+                Role::Guardian => Error::NotGuardian,
+                Role::PauseAdmin => Error::NotPauseAdmin,
+                Role::ProxyManager => Error::NotProxyManager,
+            }
+        }
+    }
+
 
     #[ink(storage)]
     pub struct MetaDefender {
         provider_map: Mapping<AccountId, ProviderInfo>,
         historical_provider_map: Mapping<AccountId, HistoricalProviderInfo>,
-        user_policies: Mapping<AccountId, Vec<PolicyInfo>>,
+        /// The policy ids of every beneficiary, by beneficiary and position in their list.
+        user_policies: Mapping<(AccountId, u64), u128>,
+        /// The number of policies listed for every beneficiary in `user_policies`.
+        user_policy_count: Mapping<AccountId, u64>,
         policies: Mapping<u128, PolicyInfo>,
+        /// The latest claim on every policy which had one.
+        claims: Mapping<u128, Claim>,
+        /// The votes cast, by policy, round and voter.
+        claim_votes: Mapping<ClaimRoundKey, bool>,
+        /// The votes either way which decide a claim.
+        vote_threshold: u32,
+        /// How long capital stays locked after it was provided, in milliseconds.
+        min_lock_period: Timestamp,
+        /// How long an underwriter waits between requesting and executing a withdrawal, in
+        /// milliseconds. While it is zero no request is needed.
+        withdrawal_cooldown: Timestamp,
+        /// When the withdrawal requested by each underwriter can be executed.
+        withdrawal_requests: Mapping<AccountId, Timestamp>,
+        /// The stoken each owner allows each spender to transfer.
+        allowances: Mapping<(AccountId, AccountId), Balance>,
+        /// The stoken objecting to a challenged claim, by policy, round and underwriter.
+        claim_objections: Mapping<ClaimRoundKey, Balance>,
+        /// Until when the stoken of every objecting underwriter stay in their account, so that
+        /// they can't object again from another one.
+        objection_locks: Mapping<AccountId, Timestamp>,
+        /// Accepted claims above this share of the staked capital can be vetoed, scaled by `Rate::ONE`.
+        large_claim_share: Balance,
+        /// The share of the stoken supply which vetoes a challenged claim, scaled by `Rate::ONE`.
+        veto_share: Balance,
 
         provider_count: u128, 
-        exchange_rate: Balance,
         policy_count: u128, 
-        acc_rps: Balance, 
-        acc_sps: Balance, 
-        acc_sps_down: Balance, 
-        token_staked_here: Balance,
-        stoken_supply: Balance, 
-        token_frozen_here: Balance,
+        junior: TranchePool,
+        senior: TranchePool,
+        /// The reward of a senior token of capital against a junior one, scaled by `Rate::ONE`.
+        senior_reward_weight: Balance,
         total_coverage: Balance, 
         k_last: u128, 
         latest_unfrozen_index: u128, 
+        /// The policies folded into `acc_sps_down` so far, i.e. the id of the next one in line.
+        unfrozen_policy_count: u128,
+        /// The share of the deposit of an expired policy paid to whoever cancels it once the
+        /// holder's day is over, scaled by `Rate::ONE`.
+        keeper_share: Balance,
     
         initial_fee: u128,
         min_fee: u128, 
+        /// The premium multiplier of every allowed policy duration in days, scaled by `Rate::ONE`.
+        policy_terms: Mapping<u32, Balance>,
     
-        judger: AccountId, 
-        official: AccountId, 
+        roles: Mapping<(Role, AccountId), bool>,
+        role_member_count: Mapping<Role, u32>,
+        pending_roles: Mapping<Role, PendingRole>,
+        paused: Mapping<PauseFlag, bool>,
     
         claimable_team_reward: Balance,
         virtual_param: Balance,
-        provider_leaving: bool,
-        historical_provider_leaving : bool,
+        locked: bool,
+        storage_version: u32,
+        /// The layout version `migrate` translated the records of an account and of a policy to,
+        /// so that passing them again doesn't decode them as the old layout.
+        migrated_accounts: Mapping<AccountId, u32>,
+        migrated_policies: Mapping<u128, u32>,
 
         is_valid_mining_proxy: Mapping<AccountId, bool>,
         erc20: Erc20Ref,
         risk_reserve: AccountId,
     }
 
+    /// Event emitted when a user buys a cover.
+    #[ink(event)]
+    pub struct PolicyBought {
+        #[ink(topic)]
+        beneficiary: AccountId,
+        payer: AccountId,
+        #[ink(topic)]
+        policy_id: u128,
+        coverage: Balance,
+        cover_fee: Balance,
+        deposit: Balance,
+        effective_until: Timestamp,
+        duration: u32,
+    }
 
-    impl MetaDefender {
+    /// Event emitted when an official allows a policy duration or changes its multiplier.
+    #[ink(event)]
+    pub struct PolicyTermSet {
+        duration: u32,
+        multiplier: Balance,
+    }
 
+    /// Event emitted when an official removes a policy duration.
+    #[ink(event)]
+    pub struct PolicyTermRemoved {
+        duration: u32,
+    }
 
-        #[ink(constructor)]
-        pub fn new(
-            official: AccountId, 
-            judger: AccountId,  
-            risk_reserve: AccountId,
-            virtual_param:Balance,
-            erc20: AccountId) -> Self {
-            let provider_map = Default::default();
-            let historical_provider_map = Default::default();
-            let user_policies = Default::default();
-            let policies = Default::default();
-            let is_valid_mining_proxy = Default::default();
-            let initial_fee = 2000;
-            let erc20 = Erc20Ref::from_account_id(erc20);
-            MetaDefender { 
-                provider_map, 
-                historical_provider_map, 
-                user_policies, 
-                policies, 
-                provider_count: 0, 
-                policy_count : 0,
-                exchange_rate: 100000, 
-                acc_rps: 0, 
-                acc_sps: 0, 
-                acc_sps_down: 0, 
-                token_staked_here: 0, 
-                stoken_supply: 0, 
-                token_frozen_here: 0, 
-                total_coverage: 0, 
-                k_last: 0, 
-                latest_unfrozen_index: 0, 
-                initial_fee, 
-                min_fee: 2000, 
-                judger, 
-                official, 
-                claimable_team_reward: 0, 
-                virtual_param, 
-                provider_leaving: false, 
-                historical_provider_leaving: false, 
-                is_valid_mining_proxy,
-                erc20,
-                risk_reserve,
-            }
+    /// Event emitted when junior stoken is transferred, minted (`from` is `None`) or burned (`to` is `None`).
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+    }
 
-        }
+    /// Event emitted when an owner allows a spender to transfer their stoken.
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        amount: Balance,
+    }
 
+    /// Event emitted when an underwriter provides capital.
+    #[ink(event)]
+    pub struct CapitalProvided {
+        #[ink(topic)]
+        provider: AccountId,
+        amount: Balance,
+        stoken_amount: Balance,
+        tranche: Tranche,
+    }
 
-        /// This message changes the judger address.
-        /// 
-        /// Only current judger can call this message, if not, return NotJudger Error.
-        #[ink(message)]
-        pub fn judger_transfer(&mut self, judger: AccountId)  -> Result<()>{
-            let caller = self.env().caller();
-            if caller != self.judger{
-                Err(Error::NotJudger)
-            } else{
-                self.judger  = judger;
-                Ok(())
-            }
-        }
+    /// Event emitted when an underwriter leaves, `frozen` being the part of the
+    /// capital that stays behind to back the active policies.
+    #[ink(event)]
+    pub struct ProviderAbolished {
+        #[ink(topic)]
+        provider: AccountId,
+        withdrawn: Balance,
+        reward: Balance,
+        frozen: Balance,
+    }
 
+    /// Event emitted when an underwriter adds capital to their position.
+    #[ink(event)]
+    pub struct CapitalIncreased {
+        #[ink(topic)]
+        provider: AccountId,
+        amount: Balance,
+        stoken_amount: Balance,
+        reward: Balance,
+    }
 
-        /// This message changes the official address.
-        /// 
-        /// Only current official can call this message, if not, return NotOfficial Error.
-        #[ink(message)]
-        pub fn official_transfer(&mut self, official: AccountId)  -> Result<()>{
-            let caller = self.env().caller();
-            if caller != self.official{
-                Err(Error::NotOfficial)
-            } else{
-                self.official  = official;
-                Ok(())
-            }
-        }
+    /// Event emitted when the reward of a position is turned into `stoken_amount` of new stoken.
+    #[ink(event)]
+    pub struct RewardsCompounded {
+        #[ink(topic)]
+        provider: AccountId,
+        reward: Balance,
+        stoken_amount: Balance,
+    }
 
-        #[ink(message)]
-        pub fn team_claim(&mut self) -> Result<()>{
-            let caller = self.env().caller();
-            if caller == self.official{
-                match self.erc20.transfer(self.official, self.claimable_team_reward){
-                    Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                    Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                    Err(_e) => return Err(Error::TransferError),
-                    Ok(_) => return Ok(()),
-                }
-            }else{
-                return Err(Error::NotOfficial);
-            }
-        }
+    /// Event emitted when an underwriter lets keepers compound their reward, or stops them.
+    #[ink(event)]
+    pub struct AutoCompoundSet {
+        #[ink(topic)]
+        provider: AccountId,
+        enabled: bool,
+    }
 
+    /// Event emitted when an underwriter withdraws a slice of their position, `frozen` being
+    /// the part of the slice that stays behind to back the active policies.
+    #[ink(event)]
+    pub struct CapitalDecreased {
+        #[ink(topic)]
+        provider: AccountId,
+        stoken_amount: Balance,
+        withdrawn: Balance,
+        reward: Balance,
+        frozen: Balance,
+    }
 
+    /// Event emitted when an underwriter asks to withdraw, `ready_at` being the earliest time they can.
+    #[ink(event)]
+    pub struct WithdrawalRequested {
+        #[ink(topic)]
+        provider: AccountId,
+        ready_at: Timestamp,
+    }
 
-        /// This message can add a mining proxy or terminate an existing mining proxy
-        #[ink(message)]
-        pub fn valid_mining_proxy_manage(&mut self, proxy: AccountId, _bool: bool)  -> Result<()>{
-            let caller = self.env().caller();
-            if caller == self.official{
-                Err(Error::NotOfficial)
-            } else{
-                self.is_valid_mining_proxy.insert(proxy, &_bool);
-                Ok(())
-            }
-        }
+    /// Event emitted when an official changes the lock period or the withdrawal cooldown.
+    #[ink(event)]
+    pub struct WithdrawalParamsSet {
+        min_lock_period: Timestamp,
+        withdrawal_cooldown: Timestamp,
+    }
 
+    /// Event emitted when a historical underwriter withdraws unfrozen capital.
+    #[ink(event)]
+    pub struct HistoricalWithdrawn {
+        #[ink(topic)]
+        provider: AccountId,
+        amount: Balance,
+    }
 
-        /// This message can return current useable capital
-        /// 
-        /// If current total staked token is larger than total coverage, return 0
-        #[ink(message)]
-        pub fn get_useable_capital(&self)  -> Balance {
-            if self.token_staked_here >= self.total_coverage{
-                self.token_staked_here - self.total_coverage
+    /// Event emitted when a policy is replaced by a new one running on from its expiry.
+    #[ink(event)]
+    pub struct PolicyRenewed {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        new_policy_id: u128,
+        cover_fee: Balance,
+        deposit: Balance,
+        effective_until: Timestamp,
+    }
+
+    /// Event emitted when a policy is cancelled, `bounty` of its deposit is paid to `caller`
+    /// and the rest is refunded to `recipient`.
+    #[ink(event)]
+    pub struct PolicyCancelled {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        caller: AccountId,
+        deposit: Balance,
+        /// The payer of the policy.
+        recipient: AccountId,
+        bounty: Balance,
+    }
+
+    /// Event emitted when an official changes the share of the deposit paid to keepers.
+    #[ink(event)]
+    pub struct KeeperShareSet {
+        share: Balance,
+    }
+
+    /// Event emitted when an official changes the reward weight of the senior tranche.
+    #[ink(event)]
+    pub struct SeniorRewardWeightSet {
+        weight: Balance,
+    }
+
+    /// Event emitted when a policy holder applies for a claim.
+    #[ink(event)]
+    pub struct ClaimApplied {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        evidence: Hash,
+        amount: Balance,
+    }
+
+    /// Event emitted when the judger accepts a claim.
+    #[ink(event)]
+    pub struct ClaimAccepted {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        /// The amount paid out.
+        amount: Balance,
+    }
+
+    /// Event emitted when the judger refuses a claim.
+    #[ink(event)]
+    pub struct ClaimRefused {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        beneficiary: AccountId,
+        reason: u32,
+    }
+
+    /// Event emitted when a beneficiary appeals a refused claim.
+    #[ink(event)]
+    pub struct ClaimAppealed {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        beneficiary: AccountId,
+    }
+
+    /// Event emitted when a committee member votes on a claim.
+    #[ink(event)]
+    pub struct ClaimVoted {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        voter: AccountId,
+        approve: bool,
+        /// The smallest amount approved in this round so far.
+        approved_amount: Balance,
+        approvals: u32,
+        rejections: u32,
+    }
+
+    /// Event emitted when an official changes the votes needed to decide a claim.
+    #[ink(event)]
+    pub struct VoteThresholdSet {
+        threshold: u32,
+    }
+
+    /// Event emitted when an accepted large claim opens for objections instead of paying out.
+    #[ink(event)]
+    pub struct ClaimChallengeOpened {
+        #[ink(topic)]
+        policy_id: u128,
+        amount: Balance,
+        ends_at: Timestamp,
+    }
+
+    /// Event emitted when an underwriter objects to a challenged claim.
+    #[ink(event)]
+    pub struct ClaimObjected {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        underwriter: AccountId,
+        stoken_amount: Balance,
+        objections: Balance,
+    }
+
+    /// Event emitted when an official changes which claims can be vetoed and by how much stoken.
+    #[ink(event)]
+    pub struct ChallengeParamsSet {
+        large_claim_share: Balance,
+        veto_share: Balance,
+    }
+
+    /// Event emitted when an undecided claim is handed over to the officials.
+    #[ink(event)]
+    pub struct ClaimEscalated {
+        #[ink(topic)]
+        policy_id: u128,
+        #[ink(topic)]
+        beneficiary: AccountId,
+    }
+
+    /// Event emitted when unused capital is sent to a mining proxy.
+    #[ink(event)]
+    pub struct MiningFundsSent {
+        #[ink(topic)]
+        proxy: AccountId,
+        amount: Balance,
+    }
+
+    /// Event emitted when the official claims the team reward.
+    #[ink(event)]
+    pub struct TeamRewardClaimed {
+        #[ink(topic)]
+        official: AccountId,
+        amount: Balance,
+    }
+
+    /// Event emitted when an official grants a role.
+    #[ink(event)]
+    pub struct RoleGranted {
+        role: Role,
+        #[ink(topic)]
+        account: AccountId,
+        #[ink(topic)]
+        sender: AccountId,
+    }
+
+    /// Event emitted when an official revokes a role.
+    #[ink(event)]
+    pub struct RoleRevoked {
+        role: Role,
+        #[ink(topic)]
+        account: AccountId,
+        #[ink(topic)]
+        sender: AccountId,
+    }
+
+    /// Event emitted when an official replaces the contract code.
+    #[ink(event)]
+    pub struct Upgraded {
+        code_hash: [u8; 32],
+    }
+
+    /// Event emitted when the storage has been migrated to the layout of the current code.
+    #[ink(event)]
+    pub struct Migrated {
+        from: u32,
+        to: u32,
+    }
+
+    /// Event emitted when a group of messages is paused.
+    #[ink(event)]
+    pub struct Paused {
+        flag: PauseFlag,
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    /// Event emitted when a group of messages is unpaused.
+    #[ink(event)]
+    pub struct Unpaused {
+        flag: PauseFlag,
+        #[ink(topic)]
+        account: AccountId,
+    }
+
+    /// Event emitted when a role holder proposes to hand over their role.
+    #[ink(event)]
+    pub struct RoleTransferProposed {
+        role: Role,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+        expires_at: Option<Timestamp>,
+    }
+
+    /// Event emitted when a pending role transfer is withdrawn.
+    #[ink(event)]
+    pub struct RoleTransferCancelled {
+        role: Role,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+    }
+
+    /// Event emitted when a privileged role changes hands.
+    #[ink(event)]
+    pub struct RoleTransferred {
+        role: Role,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+    }
+
+
+    impl MetaDefender {
+
+
+        #[ink(constructor)]
+        pub fn new(
+            official: AccountId, 
+            judger: AccountId,  
+            risk_reserve: AccountId,
+            virtual_param:Balance,
+            erc20: AccountId) -> Self {
+            let erc20 = Erc20Ref::from_account_id(erc20);
+            let mut meta_defender = MetaDefender::blank(risk_reserve, virtual_param, erc20);
+            // every role starts with its one member
+            for (role, account) in [
+                (Role::Official, official),
+                (Role::PauseAdmin, official),
+                (Role::ProxyManager, official),
+                (Role::Judger, judger),
+            ] {
+                meta_defender.roles.insert((role, account), &true);
+                meta_defender.role_member_count.insert(role, &1);
+            }
+            meta_defender.policy_terms.insert(90, &Rate::ONE);
+            meta_defender
+        }
+
+        /// A contract with no capital, policy, role nor policy term yet.
+        fn blank(risk_reserve: AccountId, virtual_param: Balance, erc20: Erc20Ref) -> Self {
+            let provider_map = Default::default();
+            let historical_provider_map = Default::default();
+            let user_policies = Default::default();
+            let policies = Default::default();
+            let is_valid_mining_proxy = Default::default();
+            let initial_fee = 2000;
+            MetaDefender { 
+                provider_map, 
+                historical_provider_map, 
+                user_policies, 
+                user_policy_count: Default::default(),
+                policies, 
+                claims: Default::default(),
+                claim_votes: Default::default(),
+                vote_threshold: 1,
+                claim_objections: Default::default(),
+                objection_locks: Default::default(),
+                allowances: Default::default(),
+                min_lock_period: 0,
+                withdrawal_cooldown: 0,
+                withdrawal_requests: Default::default(),
+                large_claim_share: 10_000,
+                veto_share: 50_000,
+                provider_count: 0, 
+                policy_count : 0,
+                junior: TranchePool::new(),
+                senior: TranchePool::new(),
+                senior_reward_weight: 50_000,
+                total_coverage: 0, 
+                k_last: 0, 
+                latest_unfrozen_index: 0, 
+                unfrozen_policy_count: 0,
+                keeper_share: Rate::ONE,
+                initial_fee, 
+                min_fee: 2000, 
+                roles: Default::default(),
+                role_member_count: Default::default(),
+                pending_roles: Default::default(),
+                paused: Default::default(),
+                policy_terms: Default::default(),
+                claimable_team_reward: 0, 
+                virtual_param, 
+                locked: false, 
+                storage_version: STORAGE_VERSION,
+                migrated_accounts: Default::default(),
+                migrated_policies: Default::default(),
+                is_valid_mining_proxy,
+                erc20,
+                risk_reserve,
+            }
+        }
+
+        /// This message tells whether `account` holds `role`.
+        #[ink(message)]
+        pub fn has_role(&self, role: Role, account: AccountId) -> bool {
+            self.roles.get((role, account)).unwrap_or(false)
+        }
+
+        /// This message grants `role` to `account`.
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn grant_role(&mut self, role: Role, account: AccountId) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if self.set_role(role, account, true)? {
+                self.emit_event(RoleGranted {
+                    role,
+                    account,
+                    sender: caller,
+                });
+            }
+            Ok(())
+        }
+
+        /// This message revokes `role` from `account`.
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        /// The last official can't be revoked, if tried, return LastOfficial Error.
+        /// While claims need the votes of several judgers, judgers can't be revoked below `vote_threshold`,
+        /// if tried, return InvalidThreshold Error.
+        #[ink(message)]
+        pub fn revoke_role(&mut self, role: Role, account: AccountId) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if role == Role::Official && self.has_role(role, account) && self.role_member_count.get(role) == Some(1) {
+                return Err(Error::LastOfficial);
+            }
+            if self.has_role(role, account) {
+                self.ensure_committee_kept(role)?;
+            }
+            if self.set_role(role, account, false)? {
+                self.emit_event(RoleRevoked {
+                    role,
+                    account,
+                    sender: caller,
+                });
+            }
+            Ok(())
+        }
+
+        fn ensure_role(&self, role: Role, account: AccountId) -> Result<()> {
+            if self.has_role(role, account) {
+                Ok(())
             } else {
-                0
+                Err(role.missing())
+            }
+        }
+
+        /// Checks that one member of `role` less still leaves enough judgers to reach `vote_threshold`.
+        ///
+        /// A single judger decides alone and may leave, claims then wait for the officials' review.
+        fn ensure_committee_kept(&self, role: Role) -> Result<()> {
+            if role == Role::Judger
+                && self.vote_threshold > 1
+                && self.role_member_count.get(role).unwrap_or(0) <= self.vote_threshold {
+                return Err(Error::InvalidThreshold);
+            }
+            Ok(())
+        }
+
+        /// Grants or revokes `role`, returns whether anything changed.
+        fn set_role(&mut self, role: Role, account: AccountId, granted: bool) -> Result<bool> {
+            if self.has_role(role, account) == granted {
+                return Ok(false);
+            }
+            let count = self.role_member_count.get(role).unwrap_or(0);
+            if granted {
+                let count = count.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+                self.roles.insert((role, account), &true);
+                self.role_member_count.insert(role, &count);
+            } else {
+                let count = count.checked_sub(1).ok_or(Error::ArithmeticOverflow)?;
+                self.roles.remove((role, account));
+                self.role_member_count.insert(role, &count);
+            }
+            Ok(true)
+        }
+
+
+        /// This message proposes `judger` to take over the judger role of the caller.
+        /// 
+        /// The role only changes hands once `judger` accepts, a proposal with `valid_for` set
+        /// can't be accepted after `valid_for` milliseconds. A new proposal replaces the pending one.
+        /// Only a judger can call this message, if not, return NotJudger Error.
+        #[ink(message)]
+        pub fn propose_judger(&mut self, judger: AccountId, valid_for: Option<Timestamp>) -> Result<()> {
+            self.propose_role(Role::Judger, judger, valid_for)
+        }
+
+        /// This message accepts the judger role proposed to the caller.
+        #[ink(message)]
+        pub fn accept_judger(&mut self) -> Result<()> {
+            self.accept_role(Role::Judger)
+        }
+
+        /// This message withdraws the pending judger proposal.
+        /// 
+        /// Only a judger can call this message, if not, return NotJudger Error.
+        #[ink(message)]
+        pub fn cancel_judger_proposal(&mut self) -> Result<()> {
+            self.cancel_role_proposal(Role::Judger)
+        }
+
+        /// This message proposes `official` to take over the official role of the caller.
+        /// 
+        /// Works like `propose_judger`.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn propose_official(&mut self, official: AccountId, valid_for: Option<Timestamp>) -> Result<()> {
+            self.propose_role(Role::Official, official, valid_for)
+        }
+
+        /// This message accepts the official role proposed to the caller.
+        #[ink(message)]
+        pub fn accept_official(&mut self) -> Result<()> {
+            self.accept_role(Role::Official)
+        }
+
+        /// This message withdraws the pending official proposal.
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn cancel_official_proposal(&mut self) -> Result<()> {
+            self.cancel_role_proposal(Role::Official)
+        }
+
+        /// This message returns the pending transfer of `role`, if any.
+        #[ink(message)]
+        pub fn get_pending_role(&self, role: Role) -> Option<PendingRole> {
+            self.pending_roles.get(role)
+        }
+
+        /// This message tells whether the messages of `flag` are paused.
+        #[ink(message)]
+        pub fn is_paused(&self, flag: PauseFlag) -> bool {
+            self.paused.get(flag).unwrap_or(false)
+        }
+
+        /// This message pauses the messages of `flag`.
+        /// 
+        /// Only a guardian or a pause admin can call this message, if not, return NotGuardian Error.
+        #[ink(message)]
+        pub fn pause(&mut self, flag: PauseFlag) -> Result<()> {
+            let caller = self.env().caller();
+            if !self.has_role(Role::PauseAdmin, caller) {
+                self.ensure_role(Role::Guardian, caller)?;
+            }
+            if !self.is_paused(flag) {
+                self.paused.insert(flag, &true);
+                self.emit_event(Paused {
+                    flag,
+                    account: caller,
+                });
+            }
+            Ok(())
+        }
+
+        /// This message unpauses the messages of `flag`.
+        /// 
+        /// Only a pause admin can call this message, if not, return NotPauseAdmin Error.
+        #[ink(message)]
+        pub fn unpause(&mut self, flag: PauseFlag) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::PauseAdmin, caller)?;
+            if self.is_paused(flag) {
+                self.paused.remove(flag);
+                self.emit_event(Unpaused {
+                    flag,
+                    account: caller,
+                });
+            }
+            Ok(())
+        }
+
+        fn ensure_not_paused(&self, flag: PauseFlag) -> Result<()> {
+            if self.is_paused(flag) {
+                Err(Error::Paused)
+            } else {
+                Ok(())
+            }
+        }
+
+        /// This message replaces the code of the contract, keeping its storage.
+        /// 
+        /// The new code takes over from the next call on. If it changes the storage layout,
+        /// its `migrate` and `finish_migration` must be called before anything else,
+        /// the messages changing the state are rejected meanwhile. A contract of version 1
+        /// upgrades to the `migration` contract first, which rewrites the root with `migrate_root`.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn upgrade(&mut self, code_hash: [u8; 32]) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            ink::env::set_code_hash(&code_hash).map_err(|_| Error::UpgradeFailed)?;
+            self.emit_event(Upgraded { code_hash });
+            Ok(())
+        }
+
+        /// This message returns the layout version of the stored data.
+        #[ink(message)]
+        pub fn get_storage_version(&self) -> u32 {
+            self.storage_version
+        }
+
+        /// This message translates the records of the given accounts and policies from the
+        /// stored layout version to the one of the current code.
+        /// 
+        /// Mappings can't be iterated, so the officials pass every provider, beneficiary and
+        /// policy id, in as many batches as needed, then call `finish_migration`. Records passed
+        /// again are skipped. Until then, the other messages changing the state return
+        /// MigrationInProgress Error.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn migrate(&mut self, accounts: Vec<AccountId>, policies: Vec<u128>) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            let from = self.storage_version;
+            if from == STORAGE_VERSION {
+                return Ok(());
+            }
+            for account in accounts {
+                if self.migrated_accounts.get(account) != Some(STORAGE_VERSION) {
+                    self.migrate_account(from, account)?;
+                    self.migrated_accounts.insert(account, &STORAGE_VERSION);
+                }
+            }
+            for id in policies {
+                if self.migrated_policies.get(id) != Some(STORAGE_VERSION) {
+                    self.migrate_policy(from, id)?;
+                    self.migrated_policies.insert(id, &STORAGE_VERSION);
+                }
+            }
+            Ok(())
+        }
+
+        /// This message records that the storage has the layout of the current code.
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn finish_migration(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            let from = self.storage_version;
+            if from != STORAGE_VERSION {
+                self.storage_version = STORAGE_VERSION;
+                self.emit_event(Migrated {
+                    from,
+                    to: STORAGE_VERSION,
+                });
+            }
+            Ok(())
+        }
+
+        fn ensure_migrated(&self) -> Result<()> {
+            if self.storage_version != STORAGE_VERSION {
+                return Err(Error::MigrationInProgress);
+            }
+            Ok(())
+        }
+
+        /// Rewrites a root of storage version 1 in the layout of the current code, for `caller`.
+        ///
+        /// ink! decodes the whole root before any message runs, so the current code can't be called
+        /// on a root of version 1. The code of the `migration` contract, whose root is the one of
+        /// version 1, runs this in between and then switches to the current code, whose `migrate`
+        /// and `finish_migration` translate the records as usual. The capital and its accumulators
+        /// go to the junior tranche and the 90 days term, the only one sold before, is allowed.
+        /// `caller` must be an official, if not, return NotOfficial Error.
+        pub fn migrate_root(caller: AccountId) -> Result<()> {
+            let old: RootV1 = ink::env::get_contract_storage(&Self::KEY)
+                .map_err(|_| Error::MigrationFailed)?
+                .ok_or(Error::MigrationFailed)?;
+            if old.storage_version != 1 {
+                return Err(Error::UnknownStorageVersion);
+            }
+            let mut root = MetaDefender::blank(old.risk_reserve, old.virtual_param, old.erc20);
+            root.ensure_role(Role::Official, caller)?;
+            root.provider_count = old.provider_count;
+            root.policy_count = old.policy_count;
+            root.junior = TranchePool {
+                exchange_rate: old.exchange_rate,
+                acc_rps: old.acc_rps,
+                acc_sps: old.acc_sps,
+                acc_sps_down: old.acc_sps_down,
+                stoken_supply: old.stoken_supply,
+                token_staked: old.token_staked_here,
+                token_frozen: old.token_frozen_here,
+                restarts: 0,
+            };
+            root.total_coverage = old.total_coverage;
+            root.k_last = old.k_last;
+            root.latest_unfrozen_index = old.latest_unfrozen_index;
+            root.initial_fee = old.initial_fee;
+            root.min_fee = old.min_fee;
+            root.claimable_team_reward = old.claimable_team_reward;
+            root.locked = old.locked;
+            root.storage_version = old.storage_version;
+            root.policy_terms.insert(90, &Rate::ONE);
+            ink::env::set_contract_storage(&Self::KEY, &root);
+            Ok(())
+        }
+
+        /// Translates the provider, historical provider and policy list records of `account`
+        /// from layout `from`.
+        ///
+        /// A version changing their encoding decodes the old type from the same storage key
+        /// here and writes the new one back.
+        fn migrate_account(&mut self, from: u32, account: AccountId) -> Result<()> {
+            match from {
+                1 => {
+                    let old: Option<ProviderInfoV1> =
+                        ink::env::get_contract_storage(&(self.provider_map.key(), account))
+                            .map_err(|_| Error::MigrationFailed)?;
+                    if let Some(old) = old {
+                        self.provider_map.insert(account, &ProviderInfo::from(old));
+                    }
+                    let old: Option<HistoricalProviderInfoV1> =
+                        ink::env::get_contract_storage(&(self.historical_provider_map.key(), account))
+                            .map_err(|_| Error::MigrationFailed)?;
+                    if let Some(old) = old {
+                        self.historical_provider_map.insert(account, &HistoricalProviderInfo::from(old));
+                    }
+                    let old: Option<Vec<PolicyInfoV1>> =
+                        ink::env::get_contract_storage(&(self.user_policies.key(), account))
+                            .map_err(|_| Error::MigrationFailed)?;
+                    if let Some(old) = old {
+                        // the list moved to one entry per policy, the policies go with `migrate_policy`
+                        ink::env::clear_contract_storage(&(self.user_policies.key(), account));
+                        for policy in old {
+                            self.list_user_policy(account, policy.id);
+                        }
+                    }
+                    Ok(())
+                }
+                STORAGE_VERSION => Ok(()),
+                _ => Err(Error::UnknownStorageVersion),
+            }
+        }
+
+        /// Translates policy `id` from layout `from`, its entry in `user_policies` goes with
+        /// the beneficiary in `migrate_account`.
+        fn migrate_policy(&mut self, from: u32, id: u128) -> Result<()> {
+            match from {
+                1 => {
+                    let old: Option<PolicyInfoV1> =
+                        ink::env::get_contract_storage(&(self.policies.key(), id))
+                            .map_err(|_| Error::MigrationFailed)?;
+                    if let Some(old) = old {
+                        // claims filed before version 2 have no record, open one as if filed now
+                        if old.in_claim_applying && self.claims.get(id).is_none() {
+                            let today = self.env().block_timestamp();
+                            self.claims.insert(id, &Claim {
+                                policy_id: id,
+                                evidence: Hash::default(),
+                                amount: old.coverage,
+                                submitted_at: today,
+                                status: ClaimStatus::Pending,
+                                reason: 0,
+                                decided_at: None,
+                                deadline: today.checked_add(CLAIM_DEADLINE).ok_or(Error::ArithmeticOverflow)?,
+                                appealed: false,
+                                round: 0,
+                                approvals: 0,
+                                rejections: 0,
+                                approved_amount: 0,
+                                challenge_ends: None,
+                                objections: 0,
+                            });
+                        }
+                        // version 1 cancelled policies in purchase order only, unfreezing them on the way
+                        if old.is_canceled {
+                            self.unfrozen_policy_count = core::cmp::max(self.unfrozen_policy_count, add(id, 1)?);
+                        }
+                        self.policies.insert(id, &PolicyInfo::from(old));
+                    }
+                    Ok(())
+                }
+                STORAGE_VERSION => Ok(()),
+                _ => Err(Error::UnknownStorageVersion),
+            }
+        }
+
+        fn propose_role(&mut self, role: Role, to: AccountId, valid_for: Option<Timestamp>) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(role, caller)?;
+            let expires_at = match valid_for {
+                Some(valid_for) => Some(
+                    self.env()
+                        .block_timestamp()
+                        .checked_add(valid_for)
+                        .ok_or(Error::ArithmeticOverflow)?,
+                ),
+                None => None,
+            };
+            self.pending_roles.insert(role, &PendingRole {
+                from: caller,
+                to,
+                expires_at,
+            });
+            self.emit_event(RoleTransferProposed {
+                role,
+                from: caller,
+                to,
+                expires_at,
+            });
+            Ok(())
+        }
+
+        fn accept_role(&mut self, role: Role) -> Result<()> {
+            let caller = self.env().caller();
+            let pending = match self.pending_roles.get(role) {
+                None => return Err(Error::NoPendingRole),
+                Some(pending) => pending,
+            };
+            if pending.to != caller {
+                return Err(Error::NotPendingAccount);
+            }
+            if let Some(expires_at) = pending.expires_at {
+                if self.env().block_timestamp() > expires_at {
+                    return Err(Error::PendingRoleExpired);
+                }
+            }
+            // the proposer may have lost the role in the meantime
+            if !self.has_role(role, pending.from) {
+                return Err(Error::NoPendingRole);
+            }
+            // a member taking over from another one leaves one member less
+            if self.has_role(role, caller) {
+                self.ensure_committee_kept(role)?;
+            }
+            self.pending_roles.remove(role);
+            self.set_role(role, caller, true)?;
+            self.set_role(role, pending.from, false)?;
+            self.emit_event(RoleTransferred {
+                role,
+                from: pending.from,
+                to: caller,
+            });
+            Ok(())
+        }
+
+        fn cancel_role_proposal(&mut self, role: Role) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(role, caller)?;
+            let pending = match self.pending_roles.get(role) {
+                None => return Err(Error::NoPendingRole),
+                Some(pending) => pending,
+            };
+            self.pending_roles.remove(role);
+            self.emit_event(RoleTransferCancelled {
+                role,
+                from: pending.from,
+                to: pending.to,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn team_claim(&mut self) -> Result<()> {
+            self.non_reentrant(|this| this.team_claim_impl())
+        }
+
+        fn team_claim_impl(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            if self.has_role(Role::Official, caller){
+                let amount = self.claimable_team_reward;
+                self.claimable_team_reward = 0;
+                self.token_transfer(caller, amount)?;
+                self.emit_event(TeamRewardClaimed {
+                    official: caller,
+                    amount,
+                });
+                Ok(())
+            }else{
+                return Err(Error::NotOfficial);
+            }
+        }
+
+
+
+        /// This message can add a mining proxy or terminate an existing mining proxy
+        /// 
+        /// Only a proxy manager can call this message, if not, return NotProxyManager Error.
+        #[ink(message)]
+        pub fn valid_mining_proxy_manage(&mut self, proxy: AccountId, _bool: bool)  -> Result<()>{
+            let caller = self.env().caller();
+            if !self.has_role(Role::ProxyManager, caller){
+                Err(Error::NotProxyManager)
+            } else{
+                self.is_valid_mining_proxy.insert(proxy, &_bool);
+                Ok(())
+            }
+        }
+
+
+        /// This message allows covers of `duration` days, priced at `multiplier` times `get_fee`.
+        /// 
+        /// `multiplier` is scaled by 100_000, setting an allowed duration again changes its multiplier.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn set_policy_term(&mut self, duration: u32, multiplier: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if duration == 0 || multiplier == 0 {
+                return Err(Error::InvalidPolicyTerm);
+            }
+            self.policy_terms.insert(duration, &multiplier);
+            self.emit_event(PolicyTermSet {
+                duration,
+                multiplier,
+            });
+            Ok(())
+        }
+
+        /// This message stops selling covers of `duration` days, existing policies are kept.
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn remove_policy_term(&mut self, duration: u32) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if self.policy_terms.get(duration).is_none() {
+                return Err(Error::UnsupportedDuration);
+            }
+            self.policy_terms.remove(duration);
+            self.emit_event(PolicyTermRemoved { duration });
+            Ok(())
+        }
+
+        /// This message returns the premium multiplier of covers of `duration` days, if allowed.
+        #[ink(message)]
+        pub fn get_policy_term(&self, duration: u32) -> Option<Balance> {
+            self.policy_terms.get(duration)
+        }
+
+        /// This message returns what `buy_cover` would charge right now for `coverage` over `duration` days.
+        /// 
+        /// Front-ends pass the returned cover fee, plus the tolerance of the user, as `max_premium`.
+        #[ink(message)]
+        pub fn get_quote(&self, coverage: Balance, duration: u32) -> Result<Quote> {
+            self.quote(coverage, duration)
+        }
+
+        fn quote(&self, coverage: Balance, duration: u32) -> Result<Quote> {
+            let useable_capital = self.get_useable_capital();
+            if useable_capital == 0 || coverage > mul_div(useable_capital, 2, 100)? {
+                return Err(Error::InsufficientCoverage);
+            }
+            let multiplier = match self.policy_terms.get(duration) {
+                None => return Err(Error::UnsupportedDuration),
+                Some(multiplier) => multiplier,
+            };
+            let premium = Rate(self.get_fee()).mul(coverage, Rounding::Up)?;
+            let cover_fee = Rate(multiplier).mul(premium, Rounding::Up)?;
+            let deposit = math::mul_div(cover_fee, 5, 100, Rounding::Up)?;
+            Ok(Quote {
+                cover_fee,
+                deposit,
+                total: add(cover_fee, deposit)?,
+            })
+        }
+
+        /// This message can return current useable capital
+        /// 
+        /// If current total staked token is larger than total coverage, return 0
+        #[ink(message)]
+        pub fn get_useable_capital(&self)  -> Balance {
+            self.token_staked_here().saturating_sub(self.total_coverage)
+        }
+
+        /// This message returns the capital behind one stoken of `tranche`, scaled by 100_000.
+        #[ink(message)]
+        pub fn get_exchange_rate(&self, tranche: Tranche) -> Balance {
+            self.pool(tranche).exchange_rate
+        }
+
+        fn pool(&self, tranche: Tranche) -> &TranchePool {
+            match tranche {
+                Tranche::Junior => &self.junior,
+                Tranche::Senior => &self.senior,
+            }
+        }
+
+        fn pool_mut(&mut self, tranche: Tranche) -> &mut TranchePool {
+            match tranche {
+                Tranche::Junior => &mut self.junior,
+                Tranche::Senior => &mut self.senior,
+            }
+        }
+
+        /// The capital staked in both tranches.
+        fn token_staked_here(&self) -> Balance {
+            self.junior.token_staked.saturating_add(self.senior.token_staked)
+        }
+
+        /// The stoken of both tranches.
+        fn stoken_supply(&self) -> Balance {
+            self.junior.stoken_supply.saturating_add(self.senior.stoken_supply)
+        }
+
+
+        /// This message can calculate the current premium rate
+        /// 
+        /// If current useable capital is zero, return 0
+        #[ink(message)]
+        pub fn get_fee(&self)  -> Balance {
+            let useable_capital = self.get_useable_capital();
+            if useable_capital != 0 {
+                self.k_last/(useable_capital.saturating_add(self.virtual_param))
+            }else{
+                0
+            }
+        }
+    
+        /// User buys a cover for themselves with the specific coverage over `duration` days
+        /// 
+        /// `duration` must be an allowed term, if not, return UnsupportedDuration Error.
+        /// If the cover fee is above `max_premium`, return SlippageExceeded Error,
+        /// if the block is later than `deadline`, return Expired Error.
+        /// `get_quote` tells the cover fee beforehand.
+        #[ink(message)]
+        pub fn buy_cover(&mut self, coverage: Balance, duration: u32, max_premium: Balance, deadline: Timestamp) -> Result<()> {
+            let caller = self.env().caller();
+            self.buy_cover_for(caller, coverage, duration, max_premium, deadline)
+        }
+
+        /// User buys a cover for `beneficiary`, paying the fee and the deposit
+        /// 
+        /// Only the beneficiary can apply for a claim and gets it paid,
+        /// the deposit goes back to the caller when the policy is cancelled.
+        /// `max_premium` and `deadline` work like in `buy_cover`.
+        #[ink(message)]
+        pub fn buy_cover_for(
+            &mut self,
+            beneficiary: AccountId,
+            coverage: Balance,
+            duration: u32,
+            max_premium: Balance,
+            deadline: Timestamp,
+        ) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::NewCovers)?;
+            if self.env().block_timestamp() > deadline {
+                return Err(Error::Expired);
+            }
+            self.non_reentrant(|this| this.buy_cover_impl(beneficiary, coverage, duration, max_premium))
+        }
+
+        fn buy_cover_impl(&mut self, beneficiary: AccountId, coverage: Balance, duration: u32, max_premium: Balance) -> Result<()> {
+            
+            let Quote { cover_fee, deposit, total: total_pay } = self.quote(coverage, duration)?;
+            if cover_fee > max_premium {
+                return Err(Error::SlippageExceeded);
+            }
+
+            let payer = self.env().caller();
+            let start_time = self.env().block_timestamp();
+            let effective_until = term_end(start_time, duration)?;
+            let draft = PolicyInfo {
+                beneficiary,
+                payer,
+                coverage,
+                deposit,
+                start_time,
+                effective_until,
+                duration,
+                id: 0,
+                latest_provider_index: 0,
+                delta_acc_sps: 0,
+                senior_delta_acc_sps: 0,
+                claimed_amount: 0,
+                is_claimed: false,
+                in_claim_applying: false,
+                is_canceled: false,
+            };
+
+            let this = self.env().account_id();
+            let policy = self.issue_policy(draft, cover_fee, |md| md.token_transfer_from(payer, this, total_pay))?;
+
+            self.emit_event(PolicyBought {
+                beneficiary,
+                payer,
+                policy_id: policy.id,
+                coverage,
+                cover_fee,
+                deposit,
+                effective_until,
+                duration,
+            });
+
+            Ok(())
+        }
+
+        /// Books `draft` as a new policy paying `cover_fee`.
+        ///
+        /// Sets the id, `latest_provider_index` and the `delta_acc_sps` of both tranches of `draft`.
+        /// Locks the coverage in the `acc_sps` of the tranches in proportion to their capital, shares
+        /// the fee between the team and the tranches, weighted by `senior_reward_weight`, and stores
+        /// the policy. Everything is computed before `settle` moves the tokens.
+        fn issue_policy(
+            &mut self,
+            mut draft: PolicyInfo,
+            cover_fee: Balance,
+            settle: impl FnOnce(&mut Self) -> Result<()>,
+        ) -> Result<PolicyInfo> {
+            let (junior_coverage, senior_coverage) =
+                split_between(draft.coverage, self.junior.token_staked, self.senior.token_staked)?;
+            let delta_acc_sps = per_stoken(junior_coverage, self.junior.stoken_supply, Rounding::Up)?;
+            let senior_delta_acc_sps = per_stoken(senior_coverage, self.senior.stoken_supply, Rounding::Up)?;
+
+            // 5% goes to the team, remaining goes to underwriters
+            let reward_for_team = mul_div(cover_fee, 5, 100)?;
+            let reward_for_providers = sub(cover_fee, reward_for_team)?;
+            let senior_weight = Rate(self.senior_reward_weight).mul(self.senior.token_staked, Rounding::Down)?;
+            let (junior_reward, senior_reward) =
+                split_between(reward_for_providers, self.junior.token_staked, senior_weight)?;
+            let delta_acc_rps = per_stoken(junior_reward, self.junior.stoken_supply, Rounding::Down)?;
+            let senior_delta_acc_rps = per_stoken(senior_reward, self.senior.stoken_supply, Rounding::Down)?;
+
+            let total_coverage = add(self.total_coverage, draft.coverage)?;
+            let acc_sps = add(self.junior.acc_sps, delta_acc_sps)?;
+            let senior_acc_sps = add(self.senior.acc_sps, senior_delta_acc_sps)?;
+            let claimable_team_reward = add(self.claimable_team_reward, reward_for_team)?;
+            let acc_rps = add(self.junior.acc_rps, delta_acc_rps)?;
+            let senior_acc_rps = add(self.senior.acc_rps, senior_delta_acc_rps)?;
+
+            settle(self)?;
+
+            self.total_coverage = total_coverage;
+            self.junior.acc_sps = acc_sps;
+            self.senior.acc_sps = senior_acc_sps;
+            self.claimable_team_reward = claimable_team_reward;
+            self.junior.acc_rps = acc_rps;
+            self.senior.acc_rps = senior_acc_rps;
+
+            draft.id = self.policy_count;
+            draft.latest_provider_index = self.provider_count;
+            draft.delta_acc_sps = delta_acc_sps;
+            draft.senior_delta_acc_sps = senior_delta_acc_sps;
+            self.save_policy(&draft);
+            self.policy_count = add(self.policy_count, 1)?;
+            Ok(draft)
+        }
+
+        /// The payer renews policy `id` for `duration` days, running on from its expiry
+        /// 
+        /// The policy is replaced by a new one with the same beneficiary covering what is left of
+        /// its coverage, priced
+        /// through `get_fee` like `buy_cover`, and its deposit is rolled over: the caller only pays
+        /// the difference, or gets back what the new deposit doesn't need.
+        /// Renewing is possible until the holder's day after expiry is over.
+        /// `max_premium` and `deadline` work like in `buy_cover`.
+        #[ink(message)]
+        pub fn renew_policy(&mut self, id: u128, duration: u32, max_premium: Balance, deadline: Timestamp) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::NewCovers)?;
+            if self.env().block_timestamp() > deadline {
+                return Err(Error::Expired);
+            }
+            self.non_reentrant(|this| this.renew_policy_impl(id, duration, max_premium))
+        }
+
+        fn renew_policy_impl(&mut self, id: u128, duration: u32, max_premium: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let today = self.env().block_timestamp();
+            let mut old = match self.policies.get(id) {
+                None => return Err(Error::NotExistedPolicy),
+                Some(p) if p.payer != caller => return Err(Error::NotPayer),
+                Some(p) if p.is_canceled => return Err(Error::AlreadyCancelledPolicy),
+                Some(p) if p.is_claimed => return Err(Error::AlreadyClaimedPolicy),
+                Some(p) if p.in_claim_applying => return Err(Error::ClaimingInProgress),
+                Some(p) if today.saturating_sub(p.effective_until) > 86_400_000 => return Err(Error::NotEffectivePolicy),
+                Some(p) => p,
+            };
+
+            let coverage = old.remaining_coverage()?;
+            let Quote { cover_fee, deposit, total } = self.quote(coverage, duration)?;
+            if cover_fee > max_premium {
+                return Err(Error::SlippageExceeded);
+            }
+            let effective_until = term_end(core::cmp::max(today, old.effective_until), duration)?;
+            let draft = PolicyInfo {
+                beneficiary: old.beneficiary,
+                payer: caller,
+                coverage,
+                deposit,
+                start_time: today,
+                effective_until,
+                duration,
+                id: 0,
+                latest_provider_index: 0,
+                delta_acc_sps: 0,
+                senior_delta_acc_sps: 0,
+                claimed_amount: 0,
+                is_claimed: false,
+                in_claim_applying: false,
+                is_canceled: false,
+            };
+
+            let this = self.env().account_id();
+            let rolled = old.deposit;
+            let policy = self.issue_policy(draft, cover_fee, |md| {
+                if total >= rolled {
+                    md.token_transfer_from(caller, this, sub(total, rolled)?)
+                } else {
+                    md.token_transfer(caller, sub(rolled, total)?)
+                }
+            })?;
+            // the old policy stops counting, its shadow is released at once like a paid claim's,
+            // as the new policy freezes the same coverage again wherever the old one is in line
+            let acc_sps_down = add(self.junior.acc_sps_down, old.delta_acc_sps)?;
+            let senior_acc_sps_down = add(self.senior.acc_sps_down, old.senior_delta_acc_sps)?;
+            self.total_coverage = sub(self.total_coverage, coverage)?;
+            self.junior.acc_sps_down = acc_sps_down;
+            self.senior.acc_sps_down = senior_acc_sps_down;
+            old.delta_acc_sps = 0;
+            old.senior_delta_acc_sps = 0;
+            old.is_canceled = true;
+            self.save_policy(&old);
+            self.unfreeze_retired(MAX_SWEEP)?;
+
+            self.emit_event(PolicyRenewed {
+                policy_id: id,
+                new_policy_id: policy.id,
+                cover_fee,
+                deposit,
+                effective_until,
+            });
+            Ok(())
+        }
+        
+        /// This message provides `amount` of capital to `tranche`, minting stoken at its exchange rate
+        /// 
+        /// The senior tranche only takes losses once the junior capital is exhausted, and earns
+        /// `senior_reward_weight` of the reward of junior capital.
+        #[ink(message)]
+        pub fn provide_capital(&mut self, amount: Balance, tranche: Tranche) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Underwriting)?;
+            self.non_reentrant(|this| this.provide_capital_impl(amount, tranche))
+        }
+
+        fn provide_capital_impl(&mut self, amount: Balance, tranche: Tranche) -> Result<()> {
+            let provider = self.env().caller();
+            let this = self.env().account_id();
+            match self.provider_map.get(provider){
+                Some(v) if v.stoken_amount != 0 => return Err(Error::ExistingUnderWriter),
+                _ => (),
+            }
+            // A returning underwriter must first withdraw the capital frozen by the previous exit.
+            match self.historical_provider_map.get(provider){
+                Some(v) if v.ftoken != 0 => return Err(Error::HistoricalCapitalNotWithdrawn),
+                Some(_v) => self.historical_provider_map.remove(provider),
+                None => (),
+            }
+
+            let pool = self.pool(tranche);
+            let stoken_amount = pool.stoken_for(amount)?;
+            let rdebt = Ray(pool.acc_rps).of(stoken_amount, Rounding::Down)?;
+            let sdebt = Ray(pool.acc_sps).of(stoken_amount, Rounding::Down)?;
+
+            self.token_transfer_from(provider, this , amount)?;
+
+            let index = self.provider_count;
+            let participation_time = self.env().block_timestamp();
+
+            let provider_info = ProviderInfo{
+                index,
+                participation_time,
+                stoken_amount,
+                rdebt,
+                sdebt,
+                auto_compound: false,
+                tranche,
+            };
+
+            self.provider_map.insert(provider, &provider_info);
+
+            let pre_useable_capital = self.get_useable_capital();
+            self.pool_mut(tranche).mint(stoken_amount, amount)?;
+            let current_useable_capital = self.get_useable_capital();
+
+            self.update_k_last_by_provider(pre_useable_capital, current_useable_capital)?; //更新kLast
+            self.provider_count = add(self.provider_count, 1)?;
+
+            self.emit_stoken_transfer(tranche, None, Some(provider), stoken_amount);
+            self.emit_event(CapitalProvided {
+                provider,
+                amount,
+                stoken_amount,
+                tranche,
+            });
+
+            Ok(())
+        }
+
+
+        fn update_k_last_by_provider(&mut self, pre_useable_capital: Balance, current_useable_capital: Balance) -> Result<()> {
+            if self.provider_count == 0 {
+                self.k_last = mul(self.initial_fee, add(current_useable_capital, self.virtual_param)?)?; 
+            }else{
+                let fee = div(self.k_last, add(pre_useable_capital, self.virtual_param)?)?;
+                self.k_last = mul(fee, add(current_useable_capital, self.virtual_param)?)?;
+            }
+            Ok(())
+        }
+
+
+        fn get_reward(&self, address: &AccountId) -> Result<Balance> {
+            match self.provider_map.get(address){
+                None =>  Ok(0),
+                Some(v) => {
+                    if v.stoken_amount != 0 {
+                        sub(Ray(self.pool(v.tranche).acc_rps).of(v.stoken_amount, Rounding::Down)?, v.rdebt)
+                    }else{
+                        Ok(0)
+                    }
+                }
+            }
+        }
+
+        #[ink(message)]
+        pub fn provider_take_reward(&mut self) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.non_reentrant(|this| this.provider_take_reward_impl())
+        }
+
+        fn provider_take_reward_impl(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            match self.provider_map.get(caller) {
+                None => Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => Err(Error::NotValidUnderwriter),
+                Some(mut v) => {
+                    let reward = self.get_reward(&caller)?;
+                    v.rdebt = Ray(self.pool(v.tranche).acc_rps).of(v.stoken_amount, Rounding::Down)?;
+                    self.provider_map.insert(caller, &v);
+                    if reward > 0 {
+                        self.token_transfer(caller, reward)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        /// This message turns the caller's pending reward into stoken at the current exchange rate
+        /// 
+        /// The reward stays in the pool as capital, the shadow of the position is kept as it is and
+        /// the lock doesn't start over. A reward worth less than one stoken is left pending.
+        /// The caller must be an underwriter, if not, return NotUnderwriter or NotValidUnderwriter Error.
+        #[ink(message)]
+        pub fn compound_rewards(&mut self) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Underwriting)?;
+            self.non_reentrant(|this| this.compound_rewards_impl())
+        }
+
+        fn compound_rewards_impl(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            match self.provider_map.get(caller) {
+                None => Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => Err(Error::NotValidUnderwriter),
+                Some(v) => self.compound_position(caller, v).map(|_| ()),
+            }
+        }
+
+        /// This message compounds the reward of the given underwriters who turned auto-compounding on
+        /// 
+        /// Anyone can call it. Accounts without a position, or which didn't turn auto-compounding on, are
+        /// skipped. At most MAX_SWEEP accounts are accepted, if more, return BatchTooLarge Error.
+        /// Returns the number of positions which got new stoken.
+        #[ink(message)]
+        pub fn compound_rewards_batch(&mut self, providers: Vec<AccountId>) -> Result<u32> {
+            if providers.len() > MAX_SWEEP as usize {
+                return Err(Error::BatchTooLarge);
+            }
+            self.ensure_not_paused(PauseFlag::Underwriting)?;
+            self.non_reentrant(|this| this.compound_rewards_batch_impl(providers))
+        }
+
+        fn compound_rewards_batch_impl(&mut self, providers: Vec<AccountId>) -> Result<u32> {
+            let mut count = 0;
+            for provider in providers {
+                let minted = match self.provider_map.get(provider) {
+                    Some(v) if v.stoken_amount != 0 && v.auto_compound => self.compound_position(provider, v)?,
+                    _ => 0,
+                };
+                if minted != 0 {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        }
+
+        /// This message lets anyone compound the caller's reward with `compound_rewards_batch`, or stops them
+        /// 
+        /// The caller must be an underwriter, if not, return NotUnderwriter or NotValidUnderwriter Error.
+        #[ink(message)]
+        pub fn set_auto_compound(&mut self, enabled: bool) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let mut v = match self.provider_map.get(caller) {
+                None => return Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => return Err(Error::NotValidUnderwriter),
+                Some(v) => v,
+            };
+            v.auto_compound = enabled;
+            self.provider_map.insert(caller, &v);
+            self.emit_event(AutoCompoundSet {
+                provider: caller,
+                enabled,
+            });
+            Ok(())
+        }
+
+        /// This message returns whether the reward of `provider` may be compounded by anyone.
+        #[ink(message)]
+        pub fn get_auto_compound(&self, provider: AccountId) -> bool {
+            self.provider_map.get(provider).is_some_and(|v| v.stoken_amount != 0 && v.auto_compound)
+        }
+
+        /// Turns the pending reward of `position` into stoken, returns the stoken minted.
+        fn compound_position(&mut self, account: AccountId, position: ProviderInfo) -> Result<Balance> {
+            let reward = self.get_reward(&account)?;
+            // a wiped out tranche can't take the reward back in
+            let exchange_rate = self.pool(position.tranche).exchange_rate;
+            if exchange_rate == 0 || Rate(exchange_rate).div(reward, Rounding::Down)? == 0 {
+                return Ok(0);
+            }
+            let tranche = position.tranche;
+            let stoken_amount = self.grow_position(account, position, reward)?;
+            self.emit_stoken_transfer(tranche, None, Some(account), stoken_amount);
+            self.emit_event(RewardsCompounded {
+                provider: account,
+                reward,
+                stoken_amount,
+            });
+            Ok(stoken_amount)
+        }
+
+        /// Adds `amount` of capital, already held by the pool, to `position` and returns the stoken minted.
+        ///
+        /// The position is indexed after the policies bought so far so that the new stoken don't take
+        /// a shadow, the shadow of the position is kept as it is and its pending reward is settled.
+        fn grow_position(&mut self, account: AccountId, mut position: ProviderInfo, amount: Balance) -> Result<Balance> {
+            let shadow = self.get_shadow(&position)?;
+            let pool = self.pool(position.tranche);
+            let stoken_amount = pool.stoken_for(amount)?;
+            let total_stoken = add(position.stoken_amount, stoken_amount)?;
+
+            position.index = self.provider_count;
+            position.stoken_amount = total_stoken;
+            position.rdebt = Ray(pool.acc_rps).of(total_stoken, Rounding::Down)?;
+            position.sdebt = sub(Ray(pool.acc_sps).of(total_stoken, Rounding::Up)?, shadow)?;
+            self.provider_map.insert(account, &position);
+
+            let pre_useable_capital = self.get_useable_capital();
+            self.pool_mut(position.tranche).mint(stoken_amount, amount)?;
+            let current_useable_capital = self.get_useable_capital();
+            self.update_k_last_by_provider(pre_useable_capital, current_useable_capital)?;
+            self.provider_count = add(self.provider_count, 1)?;
+            Ok(stoken_amount)
+        }
+
+        fn get_shadow(&self, provider: &ProviderInfo) -> Result<Balance>{
+            let pool = self.pool(provider.tranche);
+            if provider.index > self.latest_unfrozen_index{
+                sub(Ray(pool.acc_sps).of(provider.stoken_amount, Rounding::Up)?, provider.sdebt)
+            }else{
+                let delta = sub(pool.acc_sps, pool.acc_sps_down)?;
+                Ok(Ray(delta).of(provider.stoken_amount, Rounding::Up)?)
+            }
+
+        }
+
+        fn get_shadow_historical_provider(&self, historical_provider: &HistoricalProviderInfo) -> Result<Balance> {
+            let acc_sps_down = self.pool(historical_provider.tranche).acc_sps_down;
+            if historical_provider.index_before > self.latest_unfrozen_index{
+                sub(
+                    Ray(historical_provider.acc_sps_while_left).of(historical_provider.stoken_amount_before, Rounding::Up)?,
+                    historical_provider.sdebt_before,
+                )
+            }else if historical_provider.acc_sps_while_left > acc_sps_down {
+                let delta = sub(historical_provider.acc_sps_while_left, acc_sps_down)?;
+                Ok(Ray(delta).of(historical_provider.stoken_amount_before, Rounding::Up)?)
+            }else{
+                Ok(0)
+            }
+        }
+
+
+        fn register_historical_provider(&mut self, provider: &ProviderInfo, token_remain:Balance, withdrawable_capital:Balance, address: &AccountId) -> Result<()>{
+            let index_before = provider.index;
+            let stoken_amount_before = provider.stoken_amount;
+            let token_left = sub(token_remain, withdrawable_capital)?;
+            let pool = self.pool_mut(provider.tranche);
+            let ftoken = match pool.exchange_rate {
+                // the tranche was wiped out, nothing is left to freeze
+                0 => 0,
+                exchange_rate => Rate(exchange_rate).div(token_left, Rounding::Down)?,
+            };
+            let acc_sps_while_left = pool.acc_sps;
+            let sdebt_before = provider.sdebt;
+            let historical_provider = HistoricalProviderInfo{
+                index_before, 
+                stoken_amount_before,
+                ftoken,
+                acc_sps_while_left, 
+                sdebt_before, 
+                tranche: provider.tranche,
+                restarts: pool.restarts,
+            };
+            pool.token_frozen = add(pool.token_frozen, token_left)?;
+
+            self.historical_provider_map.insert(address, &historical_provider);
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn provider_abolish(&mut self) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.non_reentrant(|this| this.provider_abolish_impl())
+        }
+
+        fn provider_abolish_impl(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            match self.provider_map.get(caller) {
+                None => Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => Err(Error::NotValidUnderwriter),
+                Some(_v) if self.historical_provider_map.get(caller).is_some_and(|h| h.ftoken != 0) => {
+                    // a slice taken by `decrease_capital` is still frozen
+                    Err(Error::HistoricalCapitalNotWithdrawn)
+                }
+                Some(mut v) => {
+                    self.ensure_not_objecting(caller)?;
+                    self.take_withdrawal_request(caller, &v)?;
+                    let token_remain = Rate(self.pool(v.tranche).exchange_rate).mul(v.stoken_amount, Rounding::Down)?;
+                    let shadow = self.get_shadow(&v)?;
+                    let withdrawable_capital = token_remain.saturating_sub(shadow);
+                    let reward = self.get_reward(&caller)?;
+                    let total_withdraw = add(withdrawable_capital, reward)?;
+
+                    self.register_historical_provider(&v, token_remain, withdrawable_capital, &caller)?;
+
+                    let burned = v.stoken_amount;
+                    v.stoken_amount = 0;
+                    v.rdebt = 0;
+                    self.provider_map.insert(caller, &v);
+
+                    let pre_useable_capital = self.get_useable_capital();
+                    self.pool_mut(v.tranche).burn(burned, token_remain)?;
+                    let current_useable_capital = self.get_useable_capital();
+                    self.update_k_last_by_provider(pre_useable_capital, current_useable_capital)?;
+
+                    
+                    if total_withdraw > 0 {
+                        self.token_transfer(caller, total_withdraw)?;
+                    }
+                    self.emit_stoken_transfer(v.tranche, Some(caller), None, burned);
+                    self.emit_event(ProviderAbolished {
+                        provider: caller,
+                        withdrawn: withdrawable_capital,
+                        reward,
+                        frozen: token_remain - withdrawable_capital,
+                    });
+                    Ok(())
+                }
+            }
+        }
+
+        /// This message adds `amount` of capital to the caller's position and pays out their pending reward
+        /// 
+        /// The new stoken start clean: the shadow of the position is kept as it is, and the position
+        /// is indexed after the policies bought so far. The lock period starts over. The caller must be an underwriter, if not,
+        /// return NotUnderwriter or NotValidUnderwriter Error.
+        #[ink(message)]
+        pub fn increase_capital(&mut self, amount: Balance) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Underwriting)?;
+            self.non_reentrant(|this| this.increase_capital_impl(amount))
+        }
+
+        fn increase_capital_impl(&mut self, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let this = self.env().account_id();
+            let mut v = match self.provider_map.get(caller) {
+                None => return Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => return Err(Error::NotValidUnderwriter),
+                Some(v) => v,
+            };
+            let reward = self.get_reward(&caller)?;
+
+            self.token_transfer_from(caller, this, amount)?;
+
+            v.participation_time = self.env().block_timestamp();
+            let tranche = v.tranche;
+            let stoken_amount = self.grow_position(caller, v, amount)?;
+
+            if reward > 0 {
+                self.token_transfer(caller, reward)?;
+            }
+            self.emit_stoken_transfer(tranche, None, Some(caller), stoken_amount);
+            self.emit_event(CapitalIncreased {
+                provider: caller,
+                amount,
+                stoken_amount,
+                reward,
+            });
+            Ok(())
+        }
+
+        /// This message withdraws `stoken_amount` of the caller's position and pays out their pending reward
+        /// 
+        /// The slice carries its share of the position's shadow, which stays frozen like the capital
+        /// of a leaving underwriter until `historical_provider_withdraw` can take it. Capital frozen by
+        /// an earlier slice must be withdrawn first, if not, return HistoricalCapitalNotWithdrawn Error.
+        /// Withdrawing nothing or everything returns InvalidStokenAmount Error, use `provider_abolish` to leave.
+        #[ink(message)]
+        pub fn decrease_capital(&mut self, stoken_amount: Balance) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.non_reentrant(|this| this.decrease_capital_impl(stoken_amount))
+        }
+
+        fn decrease_capital_impl(&mut self, stoken_amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let mut v = match self.provider_map.get(caller) {
+                None => return Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => return Err(Error::NotValidUnderwriter),
+                Some(v) if stoken_amount == 0 || stoken_amount >= v.stoken_amount => return Err(Error::InvalidStokenAmount),
+                Some(v) => v,
+            };
+            match self.historical_provider_map.get(caller) {
+                Some(h) if h.ftoken != 0 => return Err(Error::HistoricalCapitalNotWithdrawn),
+                _ => (),
+            }
+            self.ensure_not_objecting(caller)?;
+            self.take_withdrawal_request(caller, &v)?;
+
+            let slice = split_position(&v, stoken_amount)?;
+            let token_remain = Rate(self.pool(v.tranche).exchange_rate).mul(stoken_amount, Rounding::Down)?;
+            let shadow = self.get_shadow(&slice)?;
+            let withdrawable_capital = token_remain.saturating_sub(shadow);
+            let reward = self.get_reward(&caller)?;
+            let total_withdraw = add(withdrawable_capital, reward)?;
+
+            self.register_historical_provider(&slice, token_remain, withdrawable_capital, &caller)?;
+
+            v.stoken_amount = sub(v.stoken_amount, stoken_amount)?;
+            v.sdebt = sub(v.sdebt, slice.sdebt)?;
+            v.rdebt = Ray(self.pool(v.tranche).acc_rps).of(v.stoken_amount, Rounding::Down)?;
+            self.provider_map.insert(caller, &v);
+
+            let pre_useable_capital = self.get_useable_capital();
+            self.pool_mut(v.tranche).burn(stoken_amount, token_remain)?;
+            let current_useable_capital = self.get_useable_capital();
+            self.update_k_last_by_provider(pre_useable_capital, current_useable_capital)?;
+
+            if total_withdraw > 0 {
+                self.token_transfer(caller, total_withdraw)?;
+            }
+            self.emit_stoken_transfer(v.tranche, Some(caller), None, stoken_amount);
+            self.emit_event(CapitalDecreased {
+                provider: caller,
+                stoken_amount,
+                withdrawn: withdrawable_capital,
+                reward,
+                frozen: token_remain - withdrawable_capital,
+            });
+            Ok(())
+        }
+
+        /// This message asks to withdraw, `provider_abolish` or `decrease_capital` can be executed once the
+        /// cooldown is over and the capital is unlocked, within WITHDRAWAL_WINDOW
+        /// 
+        /// A new request replaces the previous one. The caller must be an underwriter, if not,
+        /// return NotUnderwriter or NotValidUnderwriter Error.
+        #[ink(message)]
+        pub fn request_withdrawal(&mut self) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let v = match self.provider_map.get(caller) {
+                None => return Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => return Err(Error::NotValidUnderwriter),
+                Some(v) => v,
+            };
+            let today = self.env().block_timestamp();
+            let cooled_down = today.checked_add(self.withdrawal_cooldown).ok_or(Error::ArithmeticOverflow)?;
+            let ready_at = core::cmp::max(cooled_down, self.unlock_time(&v)?);
+            self.withdrawal_requests.insert(caller, &ready_at);
+            self.emit_event(WithdrawalRequested {
+                provider: caller,
+                ready_at,
+            });
+            Ok(())
+        }
+
+        /// This message returns when the capital of `provider` is unlocked, or None if they don't underwrite.
+        #[ink(message)]
+        pub fn get_unlock_time(&self, provider: AccountId) -> Option<Timestamp> {
+            match self.provider_map.get(provider) {
+                Some(v) if v.stoken_amount != 0 => self.unlock_time(&v).ok(),
+                _ => None,
+            }
+        }
+
+        /// This message returns the earliest time `provider` can exit
+        /// 
+        /// That is when their request is ready, or when their capital is unlocked while no request is needed.
+        /// Returns None if they don't underwrite, or need a request and have none that is still valid.
+        #[ink(message)]
+        pub fn get_exit_time(&self, provider: AccountId) -> Option<Timestamp> {
+            let unlock_time = self.get_unlock_time(provider)?;
+            if self.withdrawal_cooldown == 0 {
+                return Some(unlock_time);
+            }
+            let ready_at = self.withdrawal_requests.get(provider)?;
+            let today = self.env().block_timestamp();
+            if today > ready_at.saturating_add(WITHDRAWAL_WINDOW) {
+                return None;
+            }
+            Some(core::cmp::max(ready_at, unlock_time))
+        }
+
+        /// This message sets the lock period of provided capital and the cooldown of withdrawal requests
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn set_withdrawal_params(&mut self, min_lock_period: Timestamp, withdrawal_cooldown: Timestamp) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            self.min_lock_period = min_lock_period;
+            self.withdrawal_cooldown = withdrawal_cooldown;
+            self.emit_event(WithdrawalParamsSet {
+                min_lock_period,
+                withdrawal_cooldown,
+            });
+            Ok(())
+        }
+
+        /// This message returns the lock period and the withdrawal cooldown, in milliseconds.
+        #[ink(message)]
+        pub fn get_withdrawal_params(&self) -> (Timestamp, Timestamp) {
+            (self.min_lock_period, self.withdrawal_cooldown)
+        }
+
+        /// When the capital of `provider` is unlocked.
+        fn unlock_time(&self, provider: &ProviderInfo) -> Result<Timestamp> {
+            provider.participation_time.checked_add(self.min_lock_period).ok_or(Error::ArithmeticOverflow)
+        }
+
+        /// Checks that `account` may exit now and uses up their withdrawal request.
+        fn take_withdrawal_request(&mut self, account: AccountId, provider: &ProviderInfo) -> Result<()> {
+            let today = self.env().block_timestamp();
+            if today < self.unlock_time(provider)? {
+                return Err(Error::CapitalLocked);
+            }
+            match self.withdrawal_requests.get(account) {
+                None if self.withdrawal_cooldown == 0 => Ok(()),
+                None => Err(Error::NoWithdrawalRequest),
+                Some(ready_at) if today < ready_at => Err(Error::CapitalLocked),
+                Some(ready_at) if self.withdrawal_cooldown != 0 && today > ready_at.saturating_add(WITHDRAWAL_WINDOW) => {
+                    Err(Error::NoWithdrawalRequest)
+                }
+                Some(_) => {
+                    self.withdrawal_requests.remove(account);
+                    Ok(())
+                }
+            }
+        }
+
+        /// Moves `value` stoken from `from` to `to` together with their share of the position.
+        ///
+        /// The receiver takes over the pending reward and shadow of the stoken. If they already underwrite,
+        /// the two positions are merged like `increase_capital` does, keeping the sum of both shadows.
+        /// Like a top up, receiving stoken starts the lock of the receiver over and drops their pending
+        /// withdrawal request. Only junior stoken can be moved, senior positions hold no balance of the
+        /// token, if so, return InsufficientBalance Error. Stoken can't be sent to a senior position, if so,
+        /// return TrancheMismatch Error. Stoken stay put while withdrawals are paused.
+        fn move_stoken(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.ensure_migrated()?;
+            if from == to || value == 0 {
+                return Ok(());
+            }
+            self.ensure_not_objecting(from)?;
+            let mut sender = match self.provider_map.get(from) {
+                Some(v) if v.tranche == Tranche::Junior && v.stoken_amount >= value => v,
+                _ => return Err(Error::InsufficientBalance),
+            };
+            let slice = split_position(&sender, value)?;
+            sender.stoken_amount = sub(sender.stoken_amount, value)?;
+            sender.rdebt = sub(sender.rdebt, slice.rdebt)?;
+            sender.sdebt = sub(sender.sdebt, slice.sdebt)?;
+
+            let mut receiver = match self.provider_map.get(to) {
+                Some(v) if v.stoken_amount != 0 && v.tranche != slice.tranche => return Err(Error::TrancheMismatch),
+                Some(mut v) if v.stoken_amount != 0 => {
+                    let shadow = add(self.get_shadow(&v)?, self.get_shadow(&slice)?)?;
+                    let total_stoken = add(v.stoken_amount, value)?;
+                    v.index = self.provider_count;
+                    v.stoken_amount = total_stoken;
+                    v.rdebt = add(v.rdebt, slice.rdebt)?;
+                    // both shadows were rounded up apart, the merged one may come out a unit short
+                    v.sdebt = Ray(self.pool(v.tranche).acc_sps).of(total_stoken, Rounding::Up)?.saturating_sub(shadow);
+                    self.provider_count = add(self.provider_count, 1)?;
+                    v
+                }
+                _ => slice,
+            };
+            receiver.participation_time = self.env().block_timestamp();
+            self.withdrawal_requests.remove(to);
+            self.provider_map.insert(from, &sender);
+            self.provider_map.insert(to, &receiver);
+            self.emit_event(Transfer {
+                from: Some(from),
+                to: Some(to),
+                value,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub fn get_unfrozen_capital(&self) -> u128 {
+            let caller = self.env().caller();
+            self.unfrozen_capital_of(&caller).unwrap_or(0)
+        }
+
+        /// The capital of `address` not backing any policy, both in its position and in what it left
+        /// behind, which may be a slice taken by `decrease_capital` while the position is still open.
+        fn unfrozen_capital_of(&self, address: &AccountId) -> Result<Balance> {
+            let historical = match self.historical_provider_map.get(address) {
+                None => 0,
+                Some(v) => {
+                    let shadow = self.get_shadow_historical_provider(&v)?;
+                    let token_remain = Rate(self.frozen_exchange_rate(&v)).mul(v.ftoken, Rounding::Down)?;
+                    token_remain.saturating_sub(shadow)
+                }
+            };
+
+            let current = match self.provider_map.get(address) {
+                None => 0,
+                Some(v) => {
+                    let token_remain = Rate(self.pool(v.tranche).exchange_rate).mul(v.stoken_amount, Rounding::Down)?;
+                    let shadow = self.get_shadow(&v)?;
+                    token_remain.saturating_sub(shadow)
+                }
+            };
+            add(historical, current)
+        }
+
+        /// The exchange rate of the capital `historical` left frozen, zero once its tranche was wiped out.
+        fn frozen_exchange_rate(&self, historical: &HistoricalProviderInfo) -> Balance {
+            let pool = self.pool(historical.tranche);
+            if historical.restarts == pool.restarts {
+                pool.exchange_rate
+            } else {
+                0
+            }
+        }
+
+        #[ink(message)]
+        pub fn historical_provider_withdraw(&mut self) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.non_reentrant(|this| this.historical_provider_withdraw_impl())
+        }
+
+        fn historical_provider_withdraw_impl(&mut self) -> Result<()> {
+            let caller = self.env().caller();
+            match self.historical_provider_map.get(caller){
+                None => Err(Error::NotHistoricalUnderwriter),
+                Some(mut v) if self.frozen_exchange_rate(&v) == 0 => {
+                    // the tranche was wiped out, the frozen capital is worth nothing and the entry is closed
+                    v.ftoken = 0;
+                    self.historical_provider_map.insert(caller, &v);
+                    self.emit_event(HistoricalWithdrawn {
+                        provider: caller,
+                        amount: 0,
+                    });
+                    Ok(())
+                }
+                Some(mut v) => {
+                    let shadow = self.get_shadow_historical_provider(&v)?;
+                    let exchange_rate = Rate(self.pool(v.tranche).exchange_rate);
+                    let token_remain = exchange_rate.mul(v.ftoken, Rounding::Down)?;
+                    let ftoken_left = exchange_rate.div(shadow, Rounding::Down)?;
+
+                    if token_remain <= shadow {
+                        Err(Error::InsufficientSToken)
+                    }else {
+                        let amount = token_remain - shadow;
+                        self.token_transfer(caller, amount)?;
+                        let pool = self.pool_mut(v.tranche);
+                        pool.token_frozen = pool.token_frozen.saturating_sub(amount);
+                        v.ftoken = ftoken_left;
+                        self.historical_provider_map.insert(caller, &v);
+                        self.emit_event(HistoricalWithdrawn {
+                            provider: caller,
+                            amount,
+                        });
+                        Ok(())
+                    }
+                }
+            }
+        }
+
+        #[ink(message)]
+        pub fn try_policy_cancel(&mut self, id: u128) -> Result<()> {
+            self.non_reentrant(|this| this.try_policy_cancel_impl(id))
+        }
+
+        fn try_policy_cancel_impl(&mut self, id: u128) -> Result<()> {
+            match self.policies.get(id) {
+                None => return Err(Error::NotExistedPolicy),
+                Some(v) if v.is_canceled == true =>  return Err(Error::AlreadyCancelledPolicy),
+                Some(mut v) => {
+                    self.execute_cancel(&mut v)?;
+                    self.unfreeze_retired(MAX_SWEEP)?;
+                    Ok(())
+                }
+            }
+        }
+
+        /// This message cancels the expired policies at the front of the line and unfreezes their shadow
+        /// 
+        /// Cancelling is possible out of order, but the shadow of underwriters is unfrozen in
+        /// purchase order. This walks from the first policy not unfrozen yet, through at most
+        /// `max_count` (capped by MAX_SWEEP) policies, cancels every one anyone may cancel, paying
+        /// its deposit to the caller, and stops at the first one still running or in a claim.
+        /// Returns the number of policies unfrozen.
+        #[ink(message)]
+        pub fn cancel_expired(&mut self, max_count: u32) -> Result<u32> {
+            self.non_reentrant(|this| this.cancel_expired_impl(max_count))
+        }
+
+        fn cancel_expired_impl(&mut self, max_count: u32) -> Result<u32> {
+            let caller = self.env().caller();
+            let today = self.env().block_timestamp();
+            let mut count = 0;
+            while count < max_count.min(MAX_SWEEP) {
+                let mut policy = match self.policies.get(self.unfrozen_policy_count) {
+                    None => break,
+                    Some(p) => p,
+                };
+                if !policy.is_canceled {
+                    if !keeper_cancellable(&policy, today) {
+                        break;
+                    }
+                    self.keeper_cancel(&mut policy, caller)?;
+                }
+                self.unfreeze(&policy)?;
+                count += 1;
+            }
+            Ok(count)
+        }
+
+        /// This message returns the id of the first policy whose shadow is still frozen.
+        #[ink(message)]
+        pub fn get_unfrozen_policy_count(&self) -> u128 {
+            self.unfrozen_policy_count
+        }
+
+        /// This message cancels the given expired policies, paying the keeper share of every deposit
+        /// to the caller and refunding the rest to the payers
+        /// 
+        /// Policies which can't be cancelled by anyone yet, or are already cancelled, are skipped so
+        /// that keepers racing each other don't fail. At most MAX_SWEEP ids are accepted, if more,
+        /// return BatchTooLarge Error. Returns the number of policies cancelled.
+        #[ink(message)]
+        pub fn keeper_cancel_batch(&mut self, ids: Vec<u128>) -> Result<u32> {
+            if ids.len() > MAX_SWEEP as usize {
+                return Err(Error::BatchTooLarge);
+            }
+            self.non_reentrant(|this| this.keeper_cancel_batch_impl(ids))
+        }
+
+        fn keeper_cancel_batch_impl(&mut self, ids: Vec<u128>) -> Result<u32> {
+            let caller = self.env().caller();
+            let today = self.env().block_timestamp();
+            let mut count = 0;
+            for id in ids {
+                match self.policies.get(id) {
+                    Some(mut p) if keeper_cancellable(&p, today) => {
+                        self.keeper_cancel(&mut p, caller)?;
+                        count += 1;
+                    }
+                    _ => (),
+                }
+            }
+            self.unfreeze_retired(MAX_SWEEP)?;
+            Ok(count)
+        }
+
+        /// This message sets the share of the deposit paid to whoever cancels an expired policy
+        /// after the holder's day
+        /// 
+        /// `share` is scaled by 100_000, above that, return InvalidKeeperShare Error.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn set_keeper_share(&mut self, share: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if share > Rate::ONE {
+                return Err(Error::InvalidKeeperShare);
+            }
+            self.keeper_share = share;
+            self.emit_event(KeeperShareSet { share });
+            Ok(())
+        }
+
+        /// This message returns the share of the deposit paid to keepers, scaled by 100_000.
+        #[ink(message)]
+        pub fn get_keeper_share(&self) -> Balance {
+            self.keeper_share
+        }
+
+        /// This message sets the reward of a senior token of capital against a junior one
+        /// 
+        /// `weight` is scaled by 100_000, above that, return InvalidRewardWeight Error.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn set_senior_reward_weight(&mut self, weight: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if weight > Rate::ONE {
+                return Err(Error::InvalidRewardWeight);
+            }
+            self.senior_reward_weight = weight;
+            self.emit_event(SeniorRewardWeightSet { weight });
+            Ok(())
+        }
+
+        /// This message returns the reward weight of the senior tranche, scaled by 100_000.
+        #[ink(message)]
+        pub fn get_senior_reward_weight(&self) -> Balance {
+            self.senior_reward_weight
+        }
+
+        /// Cancels `policy` on behalf of `keeper`, who earns the keeper share of the deposit.
+        fn keeper_cancel(&mut self, policy: &mut PolicyInfo, keeper: AccountId) -> Result<()> {
+            let bounty = Rate(self.keeper_share).mul(policy.deposit, Rounding::Down)?;
+            self.do_policy_cancel(policy, keeper, bounty)
+        }
+
+        /// Cancels `policy`, paying `bounty` of its deposit to `caller` and the rest to the payer.
+        ///
+        /// Its shadow stays frozen until `unfreeze` reaches it.
+        fn do_policy_cancel(&mut self, policy: &mut PolicyInfo, caller: AccountId, bounty: Balance) -> Result<()>{
+            let refund = sub(policy.deposit, bounty)?;
+            self.total_coverage = sub(self.total_coverage, policy.remaining_coverage()?)?;
+            policy.is_canceled = true;
+            self.save_policy(policy);
+            self.update_k_last_by_cancel(self.total_coverage)?;
+
+            if bounty != 0 {
+                self.token_transfer(caller, bounty)?;
+            }
+            if refund != 0 {
+                self.token_transfer(policy.payer, refund)?;
+            }
+            self.emit_event(PolicyCancelled {
+                policy_id: policy.id,
+                caller,
+                deposit: policy.deposit,
+                recipient: policy.payer,
+                bounty,
+            });
+            Ok(())
+
+        }
+
+        /// Unfreezes the cancelled policies at the front of the line, at most `max_count`.
+        fn unfreeze_retired(&mut self, max_count: u32) -> Result<()> {
+            for _ in 0..max_count {
+                match self.policies.get(self.unfrozen_policy_count) {
+                    Some(p) if p.is_canceled => self.unfreeze(&p)?,
+                    _ => break,
+                }
+            }
+            Ok(())
+        }
+
+        /// Folds `policy`, the next one in line, into the `acc_sps_down` of both tranches.
+        ///
+        /// Providers up to `latest_unfrozen_index` are charged `acc_sps - acc_sps_down`, which
+        /// only holds if every policy bought before is folded too, hence the strict order.
+        fn unfreeze(&mut self, policy: &PolicyInfo) -> Result<()> {
+            self.junior.acc_sps_down = add(self.junior.acc_sps_down, policy.delta_acc_sps)?;
+            self.senior.acc_sps_down = add(self.senior.acc_sps_down, policy.senior_delta_acc_sps)?;
+            self.latest_unfrozen_index = policy.latest_provider_index;
+            self.unfrozen_policy_count = add(self.unfrozen_policy_count, 1)?;
+            Ok(())
+        }
+
+
+        fn update_k_last_by_cancel(&mut self, total_coverage: Balance) -> Result<()>{
+            let token_staked_here = self.token_staked_here();
+            if token_staked_here > total_coverage {
+                let useable_capital = token_staked_here - total_coverage;
+                let tentative_fee = div(self.k_last, add(useable_capital, self.virtual_param)?)?;
+                if tentative_fee < self.min_fee {
+                    self.k_last = mul(self.min_fee, add(useable_capital, self.virtual_param)?)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn execute_cancel(&mut self, policy: &mut PolicyInfo) -> Result<()> {
+            let today = self.env().block_timestamp();
+            if policy.effective_until > today {
+                return Err(Error::NotExpiredPolicy);
+            } else if policy.in_claim_applying == true {
+                return Err(Error::ClaimingInProgress);
+            } else{
+                let time_pass = today - policy.effective_until;
+                if time_pass <= 86_400_000 {
+                    // the first day belongs to the holder, the deposit is refunded to the payer
+                    let caller = self.env().caller();
+                    if caller != policy.beneficiary && caller != policy.payer {
+                        return Err(Error::OnlyPolicyHolderCanCancel);
+                    }else{
+                        match self.do_policy_cancel(policy, caller, 0){
+                            Err(e) => return Err(e),
+                            Ok(_) => return Ok(()),
+                        }
+                    }
+                } else{
+                        // afterwards anyone may cancel and earns the keeper share
+                        let caller = self.env().caller();
+                        match self.keeper_cancel(policy, caller){
+                            Err(e) => return Err(e),
+                            Ok(_) => return Ok(()),
+                        }
+                }
+            }
+        }
+
+        /// This message files a claim on policy `id` for `amount`, backed by the evidence hashed to `evidence`
+        /// 
+        /// Only the beneficiary of an effective policy can apply, and for at most what is left of its coverage,
+        /// if not, return InvalidClaimAmount Error. A claim decided before is replaced by the new one.
+        /// Filing again while a refusal could still be appealed takes the place of its appeal, otherwise
+        /// it opens a new claim.
+        #[ink(message)]
+        pub fn policy_claim_apply(&mut self, id: u128, evidence: Hash, amount: Balance) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let today = self.env().block_timestamp();
+            match self.policies.get(id) {
+                None => return Err(Error::NotExistedPolicy),
+                Some(p) if p.beneficiary != caller => return Err(Error::NotBeneficiary),
+                Some(p) if p.is_claimed == true => return Err(Error::AlreadyClaimedPolicy),
+                Some(p) if p.in_claim_applying == true => return Err(Error::InClaimingProgress),
+                Some(p) if p.is_canceled == true => return Err(Error:: AlreadyCancelledPolicy),
+                Some(p) if today > p.effective_until => return Err(Error::NotEffectivePolicy),
+                Some(p) if amount == 0 || amount > p.remaining_coverage()? => return Err(Error::InvalidClaimAmount),
+                Some(mut p) => {
+                    let previous = self.claims.get(id);
+                    let appealed = matches!(
+                        &previous,
+                        Some(c) if c.status == ClaimStatus::Refused && c.ensure_appealable(today).is_ok()
+                    );
+                    p.in_claim_applying = true;
+                    self.save_policy(&p);
+                    let round = match previous {
+                        Some(c) => c.round.checked_add(1).ok_or(Error::ArithmeticOverflow)?,
+                        None => 0,
+                    };
+                    self.claims.insert(id, &Claim {
+                        policy_id: id,
+                        evidence,
+                        amount,
+                        submitted_at: today,
+                        status: ClaimStatus::Pending,
+                        reason: 0,
+                        decided_at: None,
+                        deadline: today.checked_add(CLAIM_DEADLINE).ok_or(Error::ArithmeticOverflow)?,
+                        appealed,
+                        round,
+                        approvals: 0,
+                        rejections: 0,
+                        approved_amount: 0,
+                        challenge_ends: None,
+                        objections: 0,
+                    });
+                    self.emit_event(ClaimApplied {
+                        policy_id: id,
+                        beneficiary: caller,
+                        evidence,
+                        amount,
+                    });
+                    return Ok(());
+                }
+            }
+        }
+
+        /// This message returns the latest claim on policy `policy_id`.
+        #[ink(message)]
+        pub fn get_claim(&self, policy_id: u128) -> Option<Claim> {
+            self.claims.get(policy_id)
+        }
+        
+        /// This message refuses the claim on policy `id`, recording `reason`
+        /// 
+        /// A pending or appealed claim is decided by a judger, if not, return NotJudger Error,
+        /// and only while a single vote decides claims, if not, return CommitteeVoteRequired Error.
+        /// An escalated claim is decided by an official, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn refuse_apply(&mut self, id: u128, reason: u32) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let (p, claim) = self.claim_under_review(id)?;
+            self.ensure_sole_decider(&claim, caller)?;
+            self.refuse_claim(p, claim, reason);
+            Ok(())
+        }
+
+        fn refuse_claim(&mut self, mut p: PolicyInfo, mut claim: Claim, reason: u32) {
+            let id = p.id;
+            p.in_claim_applying = false;
+            self.save_policy(&p);
+            claim.status = ClaimStatus::Refused;
+            claim.reason = reason;
+            claim.decided_at = Some(self.env().block_timestamp());
+            self.claims.insert(id, &claim);
+            self.emit_event(ClaimRefused {
+                policy_id: id,
+                beneficiary: p.beneficiary,
+                reason,
+            });
+        }
+
+        /// This message accepts the claim on policy `id` and pays `amount` of it
+        /// 
+        /// `amount` is at most the amount asked for, if not, return InvalidClaimAmount Error.
+        /// The policy keeps covering the rest and can be claimed again until its coverage is used up.
+        /// Deciders are the same as for `refuse_apply`.
+        #[ink(message)]
+        pub fn accept_apply(&mut self, id: u128, amount: Balance) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::ClaimsPayout)?;
+            self.non_reentrant(|this| this.accept_apply_impl(id, amount))
+        }
+
+        fn accept_apply_impl(&mut self, id: u128, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let (p, claim) = self.claim_under_review(id)?;
+            self.ensure_sole_decider(&claim, caller)?;
+            self.accept_claim(p, claim, amount)
+        }
+
+        /// Accepts `claim` for `amount`, paying it unless it is large enough to be challenged first.
+        ///
+        /// Claims coming back from the officials' review are paid straight away.
+        fn accept_claim(&mut self, p: PolicyInfo, mut claim: Claim, amount: Balance) -> Result<()> {
+            if amount == 0 || amount > claim.amount {
+                return Err(Error::InvalidClaimAmount);
+            }
+            let large_claim = Rate(self.large_claim_share).mul(self.token_staked_here(), Rounding::Down)?;
+            if claim.status == ClaimStatus::Escalated || amount <= large_claim {
+                return self.pay_claim(p, claim, amount);
+            }
+            let ends_at = self.env().block_timestamp().checked_add(CHALLENGE_PERIOD).ok_or(Error::ArithmeticOverflow)?;
+            claim.status = ClaimStatus::Challenged;
+            claim.approved_amount = amount;
+            claim.challenge_ends = Some(ends_at);
+            claim.start_round()?;
+            self.claims.insert(p.id, &claim);
+            self.emit_event(ClaimChallengeOpened {
+                policy_id: p.id,
+                amount,
+                ends_at,
+            });
+            Ok(())
+        }
+
+        /// Pays `amount` of `claim` to the beneficiary of `p` and closes it.
+        fn pay_claim(&mut self, mut p: PolicyInfo, mut claim: Claim, amount: Balance) -> Result<()> {
+            let id = p.id;
+            // the paid share of the shadow is released ahead of the policy's turn in line
+            let remaining = p.remaining_coverage()?;
+            let released = mul_div(p.delta_acc_sps, amount, remaining)?;
+            let senior_released = mul_div(p.senior_delta_acc_sps, amount, remaining)?;
+            let acc_sps_down = add(self.junior.acc_sps_down, released)?;
+            let senior_acc_sps_down = add(self.senior.acc_sps_down, senior_released)?;
+            let total_coverage = sub(self.total_coverage, amount)?;
+
+            p.claimed_amount = add(p.claimed_amount, amount)?;
+            p.delta_acc_sps = sub(p.delta_acc_sps, released)?;
+            p.senior_delta_acc_sps = sub(p.senior_delta_acc_sps, senior_released)?;
+            p.in_claim_applying = false;
+            p.is_claimed = p.claimed_amount == p.coverage;
+            self.save_policy(&p);
+            claim.status = ClaimStatus::Accepted;
+            claim.decided_at = Some(self.env().block_timestamp());
+            self.claims.insert(id, &claim);
+            self.junior.acc_sps_down = acc_sps_down;
+            self.senior.acc_sps_down = senior_acc_sps_down;
+            self.total_coverage = total_coverage;
+
+            let reserve = self.token_balance_of(self.risk_reserve);
+            if reserve >= amount {
+                self.token_transfer_from(self.risk_reserve, p.beneficiary, amount)?;
+            } else {
+                self.token_transfer_from(self.risk_reserve, p.beneficiary, reserve)?;
+                let exceeded = amount - reserve;
+                self.exceeded_pay(p.beneficiary, exceeded)?;
+            }
+            self.update_k_last_by_cancel(self.total_coverage)?;
+            self.emit_event(ClaimAccepted {
+                policy_id: id,
+                beneficiary: p.beneficiary,
+                amount,
+            });
+            Ok(())
+        }
+
+        /// This message appeals the refused claim on policy `id`, putting it back before the judger
+        /// 
+        /// Only the beneficiary can appeal, once per claim and within APPEAL_WINDOW of the refusal,
+        /// if not, return AlreadyAppealed or AppealWindowClosed Error.
+        #[ink(message)]
+        pub fn appeal_claim(&mut self, id: u128) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let today = self.env().block_timestamp();
+            let mut p = self.policies.get(id).ok_or(Error::NotExistedPolicy)?;
+            if p.beneficiary != caller {
+                return Err(Error::NotBeneficiary);
+            }
+            if p.is_canceled {
+                return Err(Error::AlreadyCancelledPolicy);
+            }
+            let mut claim = match self.claims.get(id) {
+                Some(c) if c.status == ClaimStatus::Refused => c,
+                _ => return Err(Error::NotRefusedClaim),
+            };
+            claim.ensure_appealable(today)?;
+            p.in_claim_applying = true;
+            self.save_policy(&p);
+            claim.status = ClaimStatus::Appealed;
+            claim.appealed = true;
+            claim.decided_at = None;
+            claim.start_round()?;
+            claim.deadline = today.checked_add(CLAIM_DEADLINE).ok_or(Error::ArithmeticOverflow)?;
+            self.claims.insert(id, &claim);
+            self.emit_event(ClaimAppealed {
+                policy_id: id,
+                beneficiary: caller,
+            });
+            Ok(())
+        }
+
+        /// This message hands the claim on policy `id` over to the officials once it is undecided past its deadline
+        /// 
+        /// Only the beneficiary can escalate, if not, return NotBeneficiary Error. Before the deadline,
+        /// return ClaimDeadlineNotReached Error.
+        #[ink(message)]
+        pub fn escalate_claim(&mut self, id: u128) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let (p, mut claim) = self.claim_under_review(id)?;
+            if p.beneficiary != caller {
+                return Err(Error::NotBeneficiary);
+            }
+            if claim.status == ClaimStatus::Escalated {
+                return Err(Error::AlreadyEscalated);
+            }
+            if self.env().block_timestamp() <= claim.deadline {
+                return Err(Error::ClaimDeadlineNotReached);
+            }
+            claim.status = ClaimStatus::Escalated;
+            claim.start_round()?;
+            self.claims.insert(id, &claim);
+            self.emit_event(ClaimEscalated {
+                policy_id: id,
+                beneficiary: caller,
+            });
+            Ok(())
+        }
+
+        /// Loads policy `id` together with its claim, which must be waiting for a decision.
+        fn claim_under_review(&self, id: u128) -> Result<(PolicyInfo, Claim)> {
+            let p = self.policies.get(id).ok_or(Error::NotExistedPolicy)?;
+            if !p.in_claim_applying {
+                return Err(Error::NotInClaimingProgress);
+            }
+            let claim = self.claims.get(id).ok_or(Error::NotInClaimingProgress)?;
+            if claim.status == ClaimStatus::Challenged {
+                return Err(Error::ClaimChallenged);
+            }
+            Ok((p, claim))
+        }
+
+        /// Loads policy `id` together with its claim, which must be in its challenge period.
+        fn challenged_claim(&self, id: u128) -> Result<(PolicyInfo, Claim, Timestamp)> {
+            let p = self.policies.get(id).ok_or(Error::NotExistedPolicy)?;
+            match self.claims.get(id) {
+                Some(c) if c.status == ClaimStatus::Challenged => {
+                    let ends_at = c.challenge_ends.ok_or(Error::NotChallengedClaim)?;
+                    Ok((p, c, ends_at))
+                }
+                _ => Err(Error::NotChallengedClaim),
+            }
+        }
+
+        /// This message objects to the challenged claim on policy `id` with all the caller's stoken
+        /// 
+        /// Only underwriters can object, once per challenge and before it ends, if not, return
+        /// AlreadyObjected or ChallengePeriodOver Error. Once the objections reach `veto_share` of
+        /// the stoken supply, the claim is escalated to the officials instead of being paid.
+        /// The objecting stoken can't be transferred or withdrawn until the challenge period is over.
+        #[ink(message)]
+        pub fn object_claim(&mut self, id: u128) -> Result<()> {
+            self.ensure_migrated()?;
+            let caller = self.env().caller();
+            let (p, mut claim, ends_at) = self.challenged_claim(id)?;
+            if self.env().block_timestamp() > ends_at {
+                return Err(Error::ChallengePeriodOver);
+            }
+            let stoken_amount = match self.provider_map.get(caller) {
+                None => return Err(Error::NotUnderwriter),
+                Some(v) if v.stoken_amount == 0 => return Err(Error::NotValidUnderwriter),
+                Some(v) => v.stoken_amount,
+            };
+            if self.claim_objections.contains((id, claim.round, caller)) {
+                return Err(Error::AlreadyObjected);
+            }
+            self.claim_objections.insert((id, claim.round, caller), &stoken_amount);
+            let locked_until = self.objection_locks.get(caller).unwrap_or(0);
+            self.objection_locks.insert(caller, &core::cmp::max(locked_until, ends_at));
+            claim.objections = add(claim.objections, stoken_amount)?;
+            self.emit_event(ClaimObjected {
+                policy_id: id,
+                underwriter: caller,
+                stoken_amount,
+                objections: claim.objections,
+            });
+
+            let veto = Rate(self.veto_share).mul(self.stoken_supply(), Rounding::Up)?;
+            if claim.objections >= veto {
+                let today = self.env().block_timestamp();
+                claim.status = ClaimStatus::Escalated;
+                claim.challenge_ends = None;
+                claim.deadline = today.checked_add(CLAIM_DEADLINE).ok_or(Error::ArithmeticOverflow)?;
+                claim.start_round()?;
+                self.emit_event(ClaimEscalated {
+                    policy_id: id,
+                    beneficiary: p.beneficiary,
+                });
+            }
+            self.claims.insert(id, &claim);
+            Ok(())
+        }
+
+        /// Fails with StokenInObjection while the stoken of `account` object to a claim still in its challenge period.
+        fn ensure_not_objecting(&self, account: AccountId) -> Result<()> {
+            match self.objection_locks.get(account) {
+                Some(until) if self.env().block_timestamp() <= until => Err(Error::StokenInObjection),
+                _ => Ok(()),
+            }
+        }
+
+        /// This message pays the challenged claim on policy `id` once its challenge period is over without a veto
+        /// 
+        /// Anyone can call this message, before the end of the challenge period, return ChallengePeriodNotOver Error.
+        #[ink(message)]
+        pub fn execute_claim(&mut self, id: u128) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::ClaimsPayout)?;
+            self.non_reentrant(|this| this.execute_claim_impl(id))
+        }
+
+        fn execute_claim_impl(&mut self, id: u128) -> Result<()> {
+            let (p, claim, ends_at) = self.challenged_claim(id)?;
+            if self.env().block_timestamp() <= ends_at {
+                return Err(Error::ChallengePeriodNotOver);
+            }
+            let amount = claim.approved_amount;
+            self.pay_claim(p, claim, amount)
+        }
+
+        /// This message sets which accepted claims underwriters can veto and how much stoken it takes
+        /// 
+        /// Claims above `large_claim_share` of the staked capital wait out CHALLENGE_PERIOD, and are
+        /// escalated once underwriters holding `veto_share` of the stoken supply object. Both are scaled
+        /// by 100_000, a share above that or a zero `veto_share` returns InvalidChallengeParams Error.
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn set_challenge_params(&mut self, large_claim_share: Balance, veto_share: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if large_claim_share > Rate::ONE || veto_share == 0 || veto_share > Rate::ONE {
+                return Err(Error::InvalidChallengeParams);
+            }
+            self.large_claim_share = large_claim_share;
+            self.veto_share = veto_share;
+            self.emit_event(ChallengeParamsSet {
+                large_claim_share,
+                veto_share,
+            });
+            Ok(())
+        }
+
+        /// This message returns the large claim share and the veto share, both scaled by 100_000.
+        #[ink(message)]
+        pub fn get_challenge_params(&self) -> (Balance, Balance) {
+            (self.large_claim_share, self.veto_share)
+        }
+
+        /// Checks that `account` may decide `claim`: officials take over escalated claims from the judgers.
+        fn ensure_decider(&self, claim: &Claim, account: AccountId) -> Result<()> {
+            match claim.status {
+                ClaimStatus::Escalated => self.ensure_role(Role::Official, account),
+                _ => self.ensure_role(Role::Judger, account),
+            }
+        }
+
+        /// Checks that `account` may decide `claim` without a vote.
+        fn ensure_sole_decider(&self, claim: &Claim, account: AccountId) -> Result<()> {
+            self.ensure_decider(claim, account)?;
+            if claim.status != ClaimStatus::Escalated && self.vote_threshold > 1 {
+                return Err(Error::CommitteeVoteRequired);
+            }
+            Ok(())
+        }
+
+        /// This message votes on the claim on policy `id`, deciding it once `vote_threshold` votes agree
+        /// 
+        /// An approval approves paying `amount` of the claim, which is at most the amount asked for,
+        /// if not, return InvalidClaimAmount Error. Once approved, the claim pays the smallest amount
+        /// approved in the round. A rejection refuses the claim with reason 0 and ignores `amount`.
+        /// Judgers vote on pending and appealed claims, officials on escalated ones, each once per round,
+        /// if not, return AlreadyVoted Error.
+        #[ink(message)]
+        pub fn vote_claim(&mut self, id: u128, approve: bool, amount: Balance) -> Result<()> {
+            if approve {
+                self.ensure_not_paused(PauseFlag::ClaimsPayout)?;
+            }
+            self.non_reentrant(|this| this.vote_claim_impl(id, approve, amount))
+        }
+
+        fn vote_claim_impl(&mut self, id: u128, approve: bool, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let (p, mut claim) = self.claim_under_review(id)?;
+            self.ensure_decider(&claim, caller)?;
+            if self.claim_votes.contains((id, claim.round, caller)) {
+                return Err(Error::AlreadyVoted);
+            }
+            if approve {
+                if amount == 0 || amount > claim.amount {
+                    return Err(Error::InvalidClaimAmount);
+                }
+                // the first approval of the round replaces whatever an earlier round left
+                if claim.approvals == 0 || amount < claim.approved_amount {
+                    claim.approved_amount = amount;
+                }
+                claim.approvals = claim.approvals.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+            } else {
+                claim.rejections = claim.rejections.checked_add(1).ok_or(Error::ArithmeticOverflow)?;
+            }
+            self.claim_votes.insert((id, claim.round, caller), &approve);
+            self.emit_event(ClaimVoted {
+                policy_id: id,
+                voter: caller,
+                approve,
+                approved_amount: claim.approved_amount,
+                approvals: claim.approvals,
+                rejections: claim.rejections,
+            });
+
+            if claim.approvals >= self.vote_threshold {
+                let amount = claim.approved_amount;
+                self.accept_claim(p, claim, amount)
+            } else if claim.rejections >= self.vote_threshold {
+                self.refuse_claim(p, claim, 0);
+                Ok(())
+            } else {
+                self.claims.insert(id, &claim);
+                Ok(())
+            }
+        }
+
+        /// This message sets how many votes either way decide a claim
+        /// 
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        /// `threshold` is at least 1 and at most the number of judgers, if not, return InvalidThreshold Error.
+        #[ink(message)]
+        pub fn set_vote_threshold(&mut self, threshold: u32) -> Result<()> {
+            let caller = self.env().caller();
+            self.ensure_role(Role::Official, caller)?;
+            if threshold == 0 || threshold > self.role_member_count.get(Role::Judger).unwrap_or(0) {
+                return Err(Error::InvalidThreshold);
+            }
+            self.vote_threshold = threshold;
+            self.emit_event(VoteThresholdSet { threshold });
+            Ok(())
+        }
+
+        /// This message returns how many votes either way decide a claim.
+        #[ink(message)]
+        pub fn get_vote_threshold(&self) -> u32 {
+            self.vote_threshold
+        }
+
+
+        /// Writes `policy` back to `policies`, listing it for its beneficiary when it is new.
+        ///
+        /// Every mutation of a `PolicyInfo` loaded from storage must go through here,
+        /// otherwise it only lives in the local copy.
+        fn save_policy(&mut self, policy: &PolicyInfo) {
+            if !self.policies.contains(policy.id) {
+                self.list_user_policy(policy.beneficiary, policy.id);
+            }
+            self.policies.insert(policy.id, policy);
+        }
+
+        /// Appends policy `id` to the `user_policies` of `beneficiary`.
+        ///
+        /// Every policy takes its own entry, so however many covers are bought for an account,
+        /// writing one of them stays as cheap as writing the first.
+        fn list_user_policy(&mut self, beneficiary: AccountId, id: u128) {
+            let count = self.user_policy_count.get(beneficiary).unwrap_or(0);
+            self.user_policies.insert((beneficiary, count), &id);
+            self.user_policy_count.insert(beneficiary, &(count + 1));
+        }
+
+        /// This message returns how many policies were bought for `beneficiary`.
+        #[ink(message)]
+        pub fn get_user_policy_count(&self, beneficiary: AccountId) -> u64 {
+            self.user_policy_count.get(beneficiary).unwrap_or(0)
+        }
+
+        /// This message returns the id of the `index`-th policy bought for `beneficiary`, oldest first.
+        #[ink(message)]
+        pub fn get_user_policy_id(&self, beneficiary: AccountId, index: u64) -> Option<u128> {
+            self.user_policies.get((beneficiary, index))
+        }
+
+
+        /// Pays `exceeded`, the part of a claim the risk reserve couldn't pay, out of the capital.
+        ///
+        /// The junior tranche takes the loss first, the senior tranche only the part the junior
+        /// capital can't cover.
+        fn exceeded_pay(&mut self, to: AccountId, exceeded: Balance) -> Result<()> {
+            let junior_capital = add(self.junior.token_staked, self.junior.token_frozen)?;
+            let junior_loss = core::cmp::min(exceeded, junior_capital);
+            self.junior.absorb(junior_loss)?;
+            self.senior.absorb(exceeded - junior_loss)?;
+
+            self.token_transfer(to, exceeded)
+        }
+
+        #[ink(message)]
+        pub fn unused_capital_for_mining(&mut self, amount: Balance, to: AccountId) -> Result<()> {
+            self.ensure_not_paused(PauseFlag::Withdrawals)?;
+            self.non_reentrant(|this| this.unused_capital_for_mining_impl(amount, to))
+        }
+
+        fn unused_capital_for_mining_impl(&mut self, amount: Balance, to: AccountId) -> Result<()> {
+            let caller = self.env().caller();
+            if !self.has_role(Role::Judger, caller) {
+                return Err(Error::NotJudger);
+            }else {
+                match self.is_valid_mining_proxy.get(to) {
+                    None => return Err(Error::NotValidMiningProxy),
+                    Some(v) if v == false => return Err(Error::NotValidMiningProxy),
+                    Some(_v) => {
+                        self.token_transfer(to, amount)?;
+                        self.emit_event(MiningFundsSent {
+                            proxy: to,
+                            amount,
+                        });
+                        return Ok(());
+                    }
+                }
+            }
+            
+        }
+
+
+        /// Runs `f` while holding the reentrancy lock.
+        ///
+        /// Every message calling into the token goes through here, the lock is
+        /// released whatever `f` returns. None runs while the storage is being migrated.
+        fn non_reentrant<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+            if self.locked {
+                return Err(Error::ReentrantCall);
+            }
+            self.ensure_migrated()?;
+            self.locked = true;
+            let result = f(self);
+            self.locked = false;
+            result
+        }
+
+        /// Emits a MetaDefender event.
+        ///
+        /// `self.env().emit_event` can't infer the contract here since the `erc20`
+        /// dependency implements `EmitEvent` for the same environment.
+        fn emit_event<E>(&self, event: E)
+        where
+            E: Into<<MetaDefender as ink::reflect::ContractEventBase>::Type>,
+        {
+            ink::codegen::EmitEvent::<MetaDefender>::emit_event(self.env(), event);
+        }
+
+        /// Emits a Transfer event for stoken of `tranche`, only junior stoken are the PSP22 token.
+        fn emit_stoken_transfer(&self, tranche: Tranche, from: Option<AccountId>, to: Option<AccountId>, value: Balance) {
+            if tranche == Tranche::Junior {
+                self.emit_event(Transfer { from, to, value });
+            }
+        }
+
+        /// Transfers `value` of the underlying token from this contract to `to`.
+        fn token_transfer(&mut self, to: AccountId, value: Balance) -> Result<()> {
+            UnderlyingToken::transfer(&mut self.erc20, to, value).map_err(Error::from)
+        }
+
+        /// Transfers `value` of the underlying token from `from` to `to` on behalf of this contract.
+        fn token_transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> Result<()> {
+            UnderlyingToken::transfer_from(&mut self.erc20, from, to, value).map_err(Error::from)
+        }
+
+        /// Returns the underlying token balance of `owner`.
+        fn token_balance_of(&self, owner: AccountId) -> Balance {
+            UnderlyingToken::balance_of(&self.erc20, owner)
+        }
+
+
+
+
+        // #[ink(message)]
+        // pub fn get(&self) ->  Balance{
+        //     self.erc20.total_supply()        
+        // }
+
+
+        // #[ink(message)]
+        // pub fn balance_of(&self, owner: AccountId) -> Balance {
+        //     self.erc20.balance_of(owner)
+        // }
+
+        // #[ink(message)]
+        // pub fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+        //     self.erc20.allowance(owner, spender)
+        // }
+
+
+        // fn transfer(&mut self, to: AccountId, amount: Balance) {
+        //     self.erc20.transfer(to, amount);
+        // }
+
+        // #[ink(message)]
+        // pub fn approve(&mut self, 
+        //     spender: AccountId, value: Balance){
+        //     self.erc20.approve(spender, value);   
+        // }
+
+        // pub fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance ) {
+        //     self.transfer_from(from, to, value);
+        // }
+
+    }
+
+    /// The calls the contract makes into the underlying token.
+    ///
+    /// The unit tests implement it over an in-memory ledger, since the off-chain environment
+    /// can't call other contracts.
+    trait UnderlyingToken {
+        fn transfer(&mut self, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error>;
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error>;
+        fn balance_of(&self, owner: AccountId) -> Balance;
+    }
+
+    #[cfg(not(test))]
+    impl UnderlyingToken for Erc20Ref {
+        fn transfer(&mut self, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+            Erc20Ref::transfer(self, to, value)
+        }
+
+        fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+            Erc20Ref::transfer_from(self, from, to, value)
+        }
+
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            Erc20Ref::balance_of(self, owner)
+        }
+    }
+
+    /// The stoken of the junior tranche. Senior positions are kept out of the token: they count for no
+    /// balance and can't be transferred.
+    impl PSP22 for MetaDefender {
+        #[ink(message)]
+        fn total_supply(&self) -> Balance {
+            self.junior.stoken_supply
+        }
+
+        #[ink(message)]
+        fn balance_of(&self, owner: AccountId) -> Balance {
+            match self.provider_map.get(owner) {
+                Some(v) if v.tranche == Tranche::Junior => v.stoken_amount,
+                _ => 0,
+            }
+        }
+
+        #[ink(message)]
+        fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get((owner, spender)).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn transfer(&mut self, to: AccountId, value: Balance, _data: Vec<u8>) -> core::result::Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            Ok(self.move_stoken(caller, to, value)?)
+        }
+
+        #[ink(message)]
+        fn transfer_from(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+            _data: Vec<u8>,
+        ) -> core::result::Result<(), PSP22Error> {
+            let caller = self.env().caller();
+            if caller == from {
+                return Ok(self.move_stoken(from, to, value)?);
+            }
+            let allowance = self.allowance(from, caller);
+            if allowance < value {
+                return Err(PSP22Error::InsufficientAllowance);
+            }
+            self.move_stoken(from, to, value)?;
+            self.allowances.insert((from, caller), &(allowance - value));
+            self.emit_event(Approval {
+                owner: from,
+                spender: caller,
+                amount: allowance - value,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn approve(&mut self, spender: AccountId, value: Balance) -> core::result::Result<(), PSP22Error> {
+            let owner = self.env().caller();
+            self.allowances.insert((owner, spender), &value);
+            self.emit_event(Approval {
+                owner,
+                spender,
+                amount: value,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn increase_allowance(&mut self, spender: AccountId, delta_value: Balance) -> core::result::Result<(), PSP22Error> {
+            let owner = self.env().caller();
+            let allowance = self.allowance(owner, spender).saturating_add(delta_value);
+            self.approve(spender, allowance)
+        }
+
+        #[ink(message)]
+        fn decrease_allowance(&mut self, spender: AccountId, delta_value: Balance) -> core::result::Result<(), PSP22Error> {
+            let owner = self.env().caller();
+            let allowance = self
+                .allowance(owner, spender)
+                .checked_sub(delta_value)
+                .ok_or(PSP22Error::InsufficientAllowance)?;
+            self.approve(spender, allowance)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ink::codegen::Env;
+        use erc20::Erc20;
+
+        type Event = <MetaDefender as ::ink::reflect::ContractEventBase>::Type;
+
+        /// An in-memory token ledger standing in for the `Erc20Ref` calls,
+        /// since the off-chain environment cannot invoke other contracts.
+        pub(super) mod mock_erc20 {
+            use super::*;
+            use std::cell::RefCell;
+            use std::collections::BTreeMap;
+
+            thread_local! {
+                static BALANCES: RefCell<BTreeMap<AccountId, Balance>> = const { RefCell::new(BTreeMap::new()) };
+                static ALLOWANCES: RefCell<BTreeMap<(AccountId, AccountId), Balance>> = const { RefCell::new(BTreeMap::new()) };
+            }
+
+            pub fn mint(to: AccountId, value: Balance) {
+                BALANCES.with(|b| *b.borrow_mut().entry(to).or_insert(0) += value);
+            }
+
+            pub fn balance_of(owner: AccountId) -> Balance {
+                BALANCES.with(|b| b.borrow().get(&owner).copied().unwrap_or(0))
+            }
+
+            pub fn transfer(from: AccountId, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+                let from_balance = balance_of(from);
+                if from_balance < value {
+                    return Err(Erc20Error::InsufficientBalance);
+                }
+                BALANCES.with(|b| {
+                    let mut b = b.borrow_mut();
+                    b.insert(from, from_balance - value);
+                    *b.entry(to).or_insert(0) += value;
+                });
+                Ok(())
+            }
+
+            pub fn approve(owner: AccountId, spender: AccountId, value: Balance) {
+                ALLOWANCES.with(|a| a.borrow_mut().insert((owner, spender), value));
+            }
+
+            pub fn allowance(owner: AccountId, spender: AccountId) -> Balance {
+                ALLOWANCES.with(|a| a.borrow().get(&(owner, spender)).copied().unwrap_or(0))
+            }
+
+            /// Moves `value` from `from` to `to` out of the allowance of `spender`, like `Erc20::transfer_from`.
+            pub fn transfer_from(spender: AccountId, from: AccountId, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+                let allowance = allowance(from, spender);
+                if allowance < value {
+                    return Err(Erc20Error::InsufficientAllowance);
+                }
+                transfer(from, to, value)?;
+                approve(from, spender, allowance - value);
+                Ok(())
+            }
+
+            /// Moves the tokens of the contract under test, whose account id is the callee.
+            impl UnderlyingToken for Erc20Ref {
+                fn transfer(&mut self, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+                    transfer(contract_id(), to, value)
+                }
+
+                fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance) -> core::result::Result<(), Erc20Error> {
+                    transfer_from(contract_id(), from, to, value)
+                }
+
+                fn balance_of(&self, owner: AccountId) -> Balance {
+                    balance_of(owner)
+                }
+            }
+        }
+
+        fn decoded_events() -> Vec<Event> {
+            ink::env::test::recorded_events()
+                .map(|event| {
+                    <Event as scale::Decode>::decode(&mut &event.data[..])
+                        .expect("encountered invalid contract event data buffer")
+                })
+                .collect()
+        }
+
+        fn last_event() -> Event {
+            decoded_events().pop().expect("no event was emitted")
+        }
+
+        fn assert_topics(event: &ink::env::test::EmittedEvent, expected_topics: Vec<Hash>) {
+            assert_eq!(event.topics.len(), expected_topics.len(), "encountered invalid number of topics");
+            for (n, (actual_topic, expected_topic)) in
+                event.topics.iter().zip(expected_topics).enumerate()
+            {
+                let mut topic_hash = Hash::from([0x00; 32]);
+                let len = actual_topic.len();
+                topic_hash.as_mut()[0..len].copy_from_slice(&actual_topic[0..len]);
+
+                assert_eq!(
+                    topic_hash, expected_topic,
+                    "encountered invalid topic at {}",
+                    n
+                );
+            }
+        }
+
+        /// Official is bob, judger is charlie and the risk reserve is django.
+        /// Eve and frank hold 1_000_000 tokens each. Alice, django, eve and frank approved
+        /// the contract for all their tokens.
+        fn create_funded() -> MetaDefender {
+            let accounts = default_accounts();
+            // keep the contract's token balance apart from alice's
+            ink::env::test::set_callee::<ink::env::DefaultEnvironment>(AccountId::from([0xFF; 32]));
+            let meta_defender = create_meta_defender(accounts.bob, accounts.charlie, accounts.django, 10_000_000, contract_id());
+            mock_erc20::mint(accounts.eve, 1_000_000);
+            mock_erc20::mint(accounts.frank, 1_000_000);
+            for owner in [accounts.alice, accounts.django, accounts.eve, accounts.frank] {
+                mock_erc20::approve(owner, contract_id(), Balance::MAX);
+            }
+            meta_defender
+        }
+
+        /// Eve underwrites 1_000_000 and frank buys policy 0 with a coverage of 10_000,
+        /// paying a cover fee of 200 and a deposit of 10.
+        fn create_with_policy() -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            meta_defender
+        }
+
+        fn set_block_timestamp(timestamp: Timestamp) {
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(timestamp);
+        }
+
+        #[ink::test]
+        fn judger_transfer_emits_events() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Ok(()));
+            match last_event() {
+                Event::RoleTransferProposed(RoleTransferProposed { role, from, to, expires_at }) => {
+                    assert_eq!(role, Role::Judger);
+                    assert_eq!(from, accounts.charlie);
+                    assert_eq!(to, accounts.alice);
+                    assert_eq!(expires_at, None);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleTransferProposed event"),
+            }
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_judger(), Ok(()));
+            match last_event() {
+                Event::RoleTransferred(RoleTransferred { role, from, to }) => {
+                    assert_eq!(role, Role::Judger);
+                    assert_eq!(from, accounts.charlie);
+                    assert_eq!(to, accounts.alice);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleTransferred event"),
+            }
+        }
+
+        #[ink::test]
+        fn official_transfer_emits_events() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.propose_official(accounts.alice, None), Ok(()));
+            assert_eq!(meta_defender.cancel_official_proposal(), Ok(()));
+            match last_event() {
+                Event::RoleTransferCancelled(RoleTransferCancelled { role, from, to }) => {
+                    assert_eq!(role, Role::Official);
+                    assert_eq!(from, accounts.bob);
+                    assert_eq!(to, accounts.alice);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleTransferCancelled event"),
+            }
+            assert_eq!(meta_defender.get_pending_role(Role::Official), None);
+
+            assert_eq!(meta_defender.propose_official(accounts.alice, None), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_official(), Ok(()));
+            match last_event() {
+                Event::RoleTransferred(RoleTransferred { role, from, to }) => {
+                    assert_eq!(role, Role::Official);
+                    assert_eq!(from, accounts.bob);
+                    assert_eq!(to, accounts.alice);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleTransferred event"),
+            }
+            assert!(meta_defender.has_role(Role::Official, accounts.alice));
+            assert!(!meta_defender.has_role(Role::Official, accounts.bob));
+        }
+
+        #[ink::test]
+        fn wrong_judger_proposal_can_be_recovered() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            // charlie mistypes the new judger, nothing changes hands yet
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_judger(accounts.django, None), Ok(()));
+            assert!(meta_defender.has_role(Role::Judger, accounts.charlie));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.django));
+
+            // a new proposal replaces the wrong one
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Ok(()));
+            set_sender(accounts.django);
+            assert_eq!(meta_defender.accept_judger(), Err(Error::NotPendingAccount));
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_judger(), Ok(()));
+            assert!(meta_defender.has_role(Role::Judger, accounts.alice));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.charlie));
+            assert_eq!(meta_defender.get_pending_role(Role::Judger), None);
+        }
+
+        #[ink::test]
+        fn wrong_official_proposal_can_be_cancelled() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.propose_official(accounts.django, None), Ok(()));
+            assert_eq!(meta_defender.cancel_official_proposal(), Ok(()));
+            assert_eq!(meta_defender.cancel_official_proposal(), Err(Error::NoPendingRole));
+
+            set_sender(accounts.django);
+            assert_eq!(meta_defender.accept_official(), Err(Error::NoPendingRole));
+            assert!(meta_defender.has_role(Role::Official, accounts.bob));
+            assert!(!meta_defender.has_role(Role::Official, accounts.django));
+        }
+
+        #[ink::test]
+        fn expired_role_proposal_cannot_be_accepted() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_block_timestamp(1_000);
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_judger(accounts.alice, Some(500)), Ok(()));
+            assert_eq!(
+                meta_defender.get_pending_role(Role::Judger),
+                Some(PendingRole {
+                    from: accounts.charlie,
+                    to: accounts.alice,
+                    expires_at: Some(1_500),
+                })
+            );
+
+            set_block_timestamp(1_501);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_judger(), Err(Error::PendingRoleExpired));
+            assert!(meta_defender.has_role(Role::Judger, accounts.charlie));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.alice));
+        }
+
+        #[ink::test]
+        fn only_role_holder_proposes() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Err(Error::NotJudger));
+            assert_eq!(meta_defender.cancel_judger_proposal(), Err(Error::NotJudger));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_official(accounts.alice, None), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.get_pending_role(Role::Official), None);
+        }
+
+        #[ink::test]
+        fn proposal_of_revoked_holder_cannot_be_accepted() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.revoke_role(Role::Judger, accounts.charlie), Ok(()));
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_judger(), Err(Error::NoPendingRole));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.alice));
+        }
+
+        #[ink::test]
+        fn provide_capital_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            match last_event() {
+                Event::CapitalProvided(CapitalProvided { provider, amount, stoken_amount, tranche }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(amount, 1_000_000);
+                    assert_eq!(stoken_amount, 1_000_000);
+                    assert_eq!(tranche, Tranche::Junior);
+                }
+                _ => panic!("encountered unexpected event kind: expected a CapitalProvided event"),
+            }
+        }
+
+        #[ink::test]
+        fn buy_cover_emits_event() {
+            let accounts = default_accounts();
+            set_block_timestamp(1_000);
+            let _meta_defender = create_with_policy();
+
+            let emitted_events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            assert_eq!(emitted_events.len(), 3);
+            match last_event() {
+                Event::PolicyBought(PolicyBought { beneficiary, payer, policy_id, coverage, cover_fee, deposit, effective_until, duration }) => {
+                    assert_eq!(beneficiary, accounts.frank);
+                    assert_eq!(payer, accounts.frank);
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(coverage, 10_000);
+                    assert_eq!(cover_fee, 200);
+                    assert_eq!(deposit, 10);
+                    assert_eq!(effective_until, 1_000 + 90 * 86_400_000);
+                    assert_eq!(duration, 90);
+                }
+                _ => panic!("encountered unexpected event kind: expected a PolicyBought event"),
+            }
+            assert_topics(
+                &emitted_events[2],
+                vec![
+                    encoded_into_hash(&PrefixedValue {
+                        prefix: b"",
+                        value: b"MetaDefender::PolicyBought",
+                    }),
+                    encoded_into_hash(&PrefixedValue {
+                        prefix: b"MetaDefender::PolicyBought::beneficiary",
+                        value: &accounts.frank,
+                    }),
+                    encoded_into_hash(&PrefixedValue {
+                        prefix: b"MetaDefender::PolicyBought::policy_id",
+                        value: &0u128,
+                    }),
+                ],
+            );
+        }
+
+        #[ink::test]
+        fn provider_abolish_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            match last_event() {
+                Event::ProviderAbolished(ProviderAbolished { provider, withdrawn, reward, frozen }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(withdrawn, 990_000);
+                    assert_eq!(reward, 190);
+                    assert_eq!(frozen, 10_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ProviderAbolished event"),
+            }
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 990_190);
+        }
+
+        #[ink::test]
+        fn historical_provider_withdraw_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            match last_event() {
+                Event::HistoricalWithdrawn(HistoricalWithdrawn { provider, amount }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(amount, 10_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a HistoricalWithdrawn event"),
+            }
+        }
+
+        #[ink::test]
+        fn increase_capital_keeps_the_shadow_and_pays_the_reward() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.eve, 500_000);
+            let k_last = meta_defender.k_last;
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.increase_capital(1_000), Err(Error::NotUnderwriter));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.increase_capital(500_000), Ok(()));
+            match last_event() {
+                Event::CapitalIncreased(CapitalIncreased { provider, amount, stoken_amount, reward }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(amount, 500_000);
+                    assert_eq!(stoken_amount, 500_000);
+                    assert_eq!(reward, 190);
+                }
+                _ => panic!("encountered unexpected event kind: expected a CapitalIncreased event"),
+            }
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 190);
+            assert_eq!(meta_defender.junior.stoken_supply, 1_500_000);
+            assert_eq!(meta_defender.junior.token_staked, 1_500_000);
+            assert!(meta_defender.k_last > k_last);
+            assert_eq!(meta_defender.get_unfrozen_capital(), 1_490_000);
+
+            // the reward was settled, the shadow of the running policy is unchanged
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_reward(&accounts.eve), Ok(0));
+            assert_eq!(meta_defender.get_shadow(&provider), Ok(10_000));
+            assert_eq!(provider.index, 1);
+        }
+
+        #[ink::test]
+        fn decrease_capital_freezes_the_share_of_the_shadow() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let k_last = meta_defender.k_last;
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.decrease_capital(0), Err(Error::InvalidStokenAmount));
+            assert_eq!(meta_defender.decrease_capital(1_000_000), Err(Error::InvalidStokenAmount));
+            assert_eq!(meta_defender.decrease_capital(400_000), Ok(()));
+            match last_event() {
+                Event::CapitalDecreased(CapitalDecreased { provider, stoken_amount, withdrawn, reward, frozen }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(stoken_amount, 400_000);
+                    assert_eq!(withdrawn, 396_000);
+                    assert_eq!(reward, 190);
+                    assert_eq!(frozen, 4_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a CapitalDecreased event"),
+            }
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 396_190);
+            assert_eq!(meta_defender.junior.stoken_supply, 600_000);
+            assert_eq!(meta_defender.junior.token_staked, 600_000);
+            assert_eq!(meta_defender.junior.token_frozen, 4_000);
+            assert!(meta_defender.k_last < k_last);
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&provider), Ok(6_000));
+
+            // the frozen slice comes out before another one or the rest
+            assert_eq!(meta_defender.decrease_capital(1_000), Err(Error::HistoricalCapitalNotWithdrawn));
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::HistoricalCapitalNotWithdrawn));
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.get_unfrozen_capital(), 604_000);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 400_190);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 1_000_190);
+        }
+
+        #[ink::test]
+        fn withdrawal_params_are_set_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            assert_eq!(meta_defender.get_withdrawal_params(), (0, 0));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.set_withdrawal_params(1_000, 100), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_withdrawal_params(1_000, 100), Ok(()));
+            assert_eq!(meta_defender.get_withdrawal_params(), (1_000, 100));
+            match last_event() {
+                Event::WithdrawalParamsSet(WithdrawalParamsSet { min_lock_period, withdrawal_cooldown }) => {
+                    assert_eq!(min_lock_period, 1_000);
+                    assert_eq!(withdrawal_cooldown, 100);
+                }
+                _ => panic!("encountered unexpected event kind: expected a WithdrawalParamsSet event"),
+            }
+        }
+
+        #[ink::test]
+        fn capital_is_locked_after_it_is_provided() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_withdrawal_params(1_000, 0), Ok(()));
+            set_block_timestamp(500);
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            assert_eq!(meta_defender.get_unlock_time(accounts.eve), Some(1_500));
+            assert_eq!(meta_defender.get_exit_time(accounts.eve), Some(1_500));
+            assert_eq!(meta_defender.get_exit_time(accounts.alice), None);
+
+            set_block_timestamp(1_499);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::CapitalLocked));
+            assert_eq!(meta_defender.decrease_capital(1_000), Err(Error::CapitalLocked));
+
+            // topping up starts the lock over
+            mock_erc20::mint(accounts.eve, 1_000);
+            assert_eq!(meta_defender.increase_capital(1_000), Ok(()));
+            assert_eq!(meta_defender.get_unlock_time(accounts.eve), Some(2_499));
+            set_block_timestamp(2_499);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+        }
+
+        #[ink::test]
+        fn withdrawal_waits_out_the_cooldown_of_a_request() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_withdrawal_params(0, 1_000), Ok(()));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::NoWithdrawalRequest));
+            assert_eq!(meta_defender.get_exit_time(accounts.eve), None);
+            set_block_timestamp(100);
+            assert_eq!(meta_defender.request_withdrawal(), Ok(()));
+            match last_event() {
+                Event::WithdrawalRequested(WithdrawalRequested { provider, ready_at }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(ready_at, 1_100);
+                }
+                _ => panic!("encountered unexpected event kind: expected a WithdrawalRequested event"),
+            }
+            assert_eq!(meta_defender.get_exit_time(accounts.eve), Some(1_100));
+            set_block_timestamp(1_099);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::CapitalLocked));
+
+            // a request which was not used in time expires
+            set_block_timestamp(1_100 + WITHDRAWAL_WINDOW + 1);
+            assert_eq!(meta_defender.get_exit_time(accounts.eve), None);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::NoWithdrawalRequest));
+
+            assert_eq!(meta_defender.request_withdrawal(), Ok(()));
+            set_block_timestamp(1_100 + WITHDRAWAL_WINDOW + 1_001);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(meta_defender.get_exit_time(accounts.eve), None);
+        }
+
+        #[ink::test]
+        fn received_stoken_can_not_leave_on_a_matured_request() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.alice, 10);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provide_capital(10, Tranche::Junior), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_withdrawal_params(0, 1_000), Ok(()));
+            set_sender(accounts.alice);
+            set_block_timestamp(100);
+            assert_eq!(meta_defender.request_withdrawal(), Ok(()));
+            set_block_timestamp(1_100);
+            assert_eq!(meta_defender.get_exit_time(accounts.alice), Some(1_100));
+
+            // the request of the dust position doesn't carry over to what it receives
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 500_000, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.get_exit_time(accounts.alice), None);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::NoWithdrawalRequest));
+
+            // nor does an older lock of the sender
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_withdrawal_params(1_000, 0), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.django, 100_000, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.get_unlock_time(accounts.django), Some(2_100));
+            assert_eq!(meta_defender.get_unlock_time(accounts.alice), Some(2_100));
+            set_sender(accounts.django);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::CapitalLocked));
+        }
+
+        #[ink::test]
+        fn compound_rewards_turns_the_reward_into_stoken() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let before = meta_defender.provider_map.get(accounts.eve).unwrap();
+            let shadow = meta_defender.get_shadow(&before).unwrap();
+            assert_eq!(meta_defender.get_reward(&accounts.eve), Ok(190));
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.compound_rewards(), Err(Error::NotUnderwriter));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.compound_rewards(), Ok(()));
+            match last_event() {
+                Event::RewardsCompounded(RewardsCompounded { provider, reward, stoken_amount }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert_eq!(reward, 190);
+                    assert_eq!(stoken_amount, 190);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RewardsCompounded event"),
+            }
+            assert_eq!(meta_defender.balance_of(accounts.eve), 1_000_190);
+            assert_eq!(meta_defender.total_supply(), 1_000_190);
+            assert_eq!(meta_defender.junior.token_staked, 1_000_190);
+            assert_eq!(meta_defender.get_reward(&accounts.eve), Ok(0));
+            let after = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&after), Ok(shadow));
+
+            // nothing left to compound
+            let events = decoded_events().len();
+            assert_eq!(meta_defender.compound_rewards(), Ok(()));
+            assert_eq!(decoded_events().len(), events);
+        }
+
+        #[ink::test]
+        fn compound_rewards_batch_only_compounds_opted_in_positions() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.set_auto_compound(true), Err(Error::NotUnderwriter));
+            assert_eq!(meta_defender.compound_rewards_batch(vec![accounts.eve, accounts.alice]), Ok(0));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.set_auto_compound(true), Ok(()));
+            assert!(meta_defender.get_auto_compound(accounts.eve));
+            match last_event() {
+                Event::AutoCompoundSet(AutoCompoundSet { provider, enabled }) => {
+                    assert_eq!(provider, accounts.eve);
+                    assert!(enabled);
+                }
+                _ => panic!("encountered unexpected event kind: expected an AutoCompoundSet event"),
+            }
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.compound_rewards_batch(vec![accounts.alice; 51]), Err(Error::BatchTooLarge));
+            assert_eq!(meta_defender.compound_rewards_batch(vec![accounts.eve, accounts.alice]), Ok(1));
+            assert_eq!(meta_defender.balance_of(accounts.eve), 1_000_190);
+            assert_eq!(meta_defender.compound_rewards_batch(vec![accounts.eve]), Ok(0));
+
+            // the flag stays with the account, not with the stoken it sends
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 1_000, Vec::new()), Ok(()));
+            assert!(!meta_defender.get_auto_compound(accounts.alice));
+            assert_eq!(meta_defender.set_auto_compound(false), Ok(()));
+            assert!(!meta_defender.get_auto_compound(accounts.eve));
+        }
+
+        /// Eve underwrites `junior` in the junior tranche and alice `senior` in the senior one.
+        fn create_with_tranches(junior: Balance, senior: Balance) -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            mock_erc20::mint(accounts.alice, senior);
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(junior, Tranche::Junior), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provide_capital(senior, Tranche::Senior), Ok(()));
+            meta_defender
+        }
+
+        #[ink::test]
+        fn senior_capital_earns_the_weighted_reward() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(1_000_000, 1_000_000);
+            let quote = meta_defender.get_quote(10_000, 90).unwrap();
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+
+            // equal capital backs the coverage equally
+            let policy = stored_policy(&meta_defender, 0);
+            assert_eq!(policy.delta_acc_sps, policy.senior_delta_acc_sps);
+            assert_ne!(policy.delta_acc_sps, 0);
+
+            // a senior token of capital earns half of a junior one by default
+            let reward = quote.cover_fee - quote.cover_fee * 5 / 100;
+            let senior_reward = meta_defender.get_reward(&accounts.alice).unwrap();
+            let junior_reward = meta_defender.get_reward(&accounts.eve).unwrap();
+            assert_eq!(senior_reward, reward / 3);
+            assert_eq!(junior_reward, reward - reward / 3);
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_senior_reward_weight(0), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.get_reward(&accounts.alice), Ok(senior_reward));
+        }
+
+        #[ink::test]
+        fn senior_reward_weight_is_set_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            assert_eq!(meta_defender.get_senior_reward_weight(), 50_000);
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.set_senior_reward_weight(20_000), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_senior_reward_weight(Rate::ONE + 1), Err(Error::InvalidRewardWeight));
+            assert_eq!(meta_defender.set_senior_reward_weight(20_000), Ok(()));
+            assert_eq!(meta_defender.get_senior_reward_weight(), 20_000);
+            match last_event() {
+                Event::SeniorRewardWeightSet(SeniorRewardWeightSet { weight }) => assert_eq!(weight, 20_000),
+                _ => panic!("encountered unexpected event kind: expected a SeniorRewardWeightSet event"),
+            }
+        }
+
+        #[ink::test]
+        fn junior_tranche_takes_losses_first() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(10_000, 1_000_000);
+            mock_erc20::mint(accounts.django, 15_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(20_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 20_000), Ok(()));
+            let balance = mock_erc20::balance_of(accounts.frank);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 20_000), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance + 20_000);
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Junior), 50_000);
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Senior), Rate::ONE);
+            assert_eq!(meta_defender.junior.token_staked, 5_000);
+            assert_eq!(meta_defender.senior.token_staked, 1_000_000);
+        }
+
+        #[ink::test]
+        fn senior_tranche_takes_what_junior_capital_cannot() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(10_000, 1_000_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(20_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 20_000), Ok(()));
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 20_000), Ok(()));
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Junior), 0);
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Senior), 99_000);
+            assert_eq!(meta_defender.junior.token_staked, 0);
+            assert_eq!(meta_defender.senior.token_staked, 990_000);
+        }
+
+        #[ink::test]
+        fn positions_in_a_wiped_out_tranche_are_closed() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(10_000, 1_000_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(20_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.decrease_capital(1_000), Ok(()));
+            assert_ne!(meta_defender.historical_provider_map.get(accounts.eve).unwrap().ftoken, 0);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 20_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 20_000), Ok(()));
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Junior), 0);
+
+            set_sender(accounts.eve);
+            let reward = meta_defender.get_reward(&accounts.eve).unwrap();
+            assert_ne!(reward, 0);
+            assert_eq!(meta_defender.compound_rewards(), Ok(()));
+            assert_eq!(meta_defender.balance_of(accounts.eve), 9_000);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            assert_eq!(meta_defender.historical_provider_map.get(accounts.eve).unwrap().ftoken, 0);
+            let balance = mock_erc20::balance_of(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), balance + reward);
+            assert_eq!(meta_defender.historical_provider_map.get(accounts.eve).unwrap().ftoken, 0);
+            assert_eq!(meta_defender.junior.stoken_supply, 0);
+        }
+
+        #[ink::test]
+        fn wiped_out_tranche_takes_capital_again() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(10_000, 1_000_000);
+            mock_erc20::mint(accounts.bob, 2_000);
+            mock_erc20::approve(accounts.bob, contract_id(), Balance::MAX);
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.provide_capital(2_000, Tranche::Junior), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(20_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_ne!(meta_defender.historical_provider_map.get(accounts.bob).unwrap().ftoken, 0);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 20_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 20_000), Ok(()));
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Junior), 0);
+
+            // the tranche takes no capital while worthless stoken are left
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Err(Error::WipedOutTranche));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(meta_defender.get_exchange_rate(Tranche::Junior), Rate::ONE);
+
+            // capital frozen before the wipe out stays worthless
+            let balance = mock_erc20::balance_of(accounts.bob);
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.bob), balance);
+            assert_eq!(meta_defender.historical_provider_map.get(accounts.bob).unwrap().ftoken, 0);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Ok(()));
+            assert_eq!(meta_defender.balance_of(accounts.frank), 1_000);
+            assert_eq!(meta_defender.total_supply(), 1_000);
+        }
+
+        #[ink::test]
+        fn stoken_stay_in_their_tranche() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_tranches(1_000_000, 1_000_000);
+            // only junior stoken are the token
+            assert_eq!(meta_defender.total_supply(), 1_000_000);
+            assert_eq!(meta_defender.balance_of(accounts.alice), 0);
+            assert_eq!(meta_defender.provider_map.get(accounts.alice).unwrap().stoken_amount, 1_000_000);
+            let minted = |e: &Event| matches!(e, Event::Transfer(Transfer { to, .. }) if *to == Some(accounts.alice));
+            assert!(!decoded_events().iter().any(minted));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.transfer(accounts.django, 1_000, Vec::new()), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(meta_defender.approve(accounts.bob, 1_000), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(
+                meta_defender.transfer_from(accounts.alice, accounts.django, 1_000, Vec::new()),
+                Err(PSP22Error::InsufficientBalance)
+            );
+
+            set_sender(accounts.eve);
+            assert_eq!(
+                meta_defender.transfer(accounts.alice, 1_000, Vec::new()),
+                Err(PSP22Error::Custom(String::from("TrancheMismatch")))
+            );
+            assert_eq!(meta_defender.transfer(accounts.django, 1_000, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.provider_map.get(accounts.django).unwrap().tranche, Tranche::Junior);
+        }
+
+        #[ink::test]
+        fn stoken_is_minted_and_burned() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            assert_eq!(meta_defender.total_supply(), 1_000_000);
+            assert_eq!(meta_defender.balance_of(accounts.eve), 1_000_000);
+            assert_eq!(meta_defender.balance_of(accounts.alice), 0);
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.decrease_capital(100_000), Ok(()));
+            let events = decoded_events();
+            match &events[events.len() - 2] {
+                Event::Transfer(Transfer { from, to, value }) => {
+                    assert_eq!(*from, Some(accounts.eve));
+                    assert_eq!(*to, None);
+                    assert_eq!(*value, 100_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a Transfer event"),
+            }
+            assert_eq!(meta_defender.total_supply(), 900_000);
+            assert_eq!(meta_defender.balance_of(accounts.eve), 900_000);
+        }
+
+        #[ink::test]
+        fn stoken_transfer_moves_reward_and_shadow() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 1_000_001, Vec::new()), Err(PSP22Error::InsufficientBalance));
+            assert_eq!(meta_defender.transfer(accounts.alice, 400_000, Vec::new()), Ok(()));
+            match last_event() {
+                Event::Transfer(Transfer { from, to, value }) => {
+                    assert_eq!(from, Some(accounts.eve));
+                    assert_eq!(to, Some(accounts.alice));
+                    assert_eq!(value, 400_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a Transfer event"),
+            }
+            assert_eq!(meta_defender.balance_of(accounts.alice), 400_000);
+            assert_eq!(meta_defender.total_supply(), 1_000_000);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            let alice = meta_defender.provider_map.get(accounts.alice).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(6_000));
+            assert_eq!(meta_defender.get_shadow(&alice), Ok(4_000));
+            assert_eq!(meta_defender.get_reward(&accounts.eve), Ok(114));
+            assert_eq!(meta_defender.get_reward(&accounts.alice), Ok(76));
+
+            // the receiver leaves with the frozen share of what they got
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 396_076);
+            assert_eq!(meta_defender.junior.token_frozen, 4_000);
+        }
+
+        #[ink::test]
+        fn stoken_transfer_merges_into_an_open_position() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.alice, 100_000);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provide_capital(100_000, Tranche::Junior), Ok(()));
+            let alice = meta_defender.provider_map.get(accounts.alice).unwrap();
+            assert_eq!(meta_defender.get_shadow(&alice), Ok(0));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 500_000, Vec::new()), Ok(()));
+            let alice = meta_defender.provider_map.get(accounts.alice).unwrap();
+            assert_eq!(alice.stoken_amount, 600_000);
+            assert_eq!(alice.index, 2);
+            assert_eq!(meta_defender.get_shadow(&alice), Ok(5_000));
+            assert_eq!(meta_defender.get_reward(&accounts.alice), Ok(95));
+        }
+
+        #[ink::test]
+        fn successive_transfers_merge_into_an_open_position() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 10, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.transfer(accounts.alice, 999_980, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.transfer(accounts.alice, 10, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.balance_of(accounts.alice), 1_000_000);
+            assert_eq!(meta_defender.balance_of(accounts.eve), 0);
+            let alice = meta_defender.provider_map.get(accounts.alice).unwrap();
+            assert_eq!(meta_defender.get_shadow(&alice), Ok(10_000));
+            assert_eq!(meta_defender.get_reward(&accounts.alice), Ok(190));
+        }
+
+        #[ink::test]
+        fn stoken_transfer_from_spends_the_allowance() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.approve(accounts.bob, 300_000), Ok(()));
+            assert_eq!(meta_defender.increase_allowance(accounts.bob, 100_000), Ok(()));
+            assert_eq!(meta_defender.decrease_allowance(accounts.bob, 500_000), Err(PSP22Error::InsufficientAllowance));
+            assert_eq!(meta_defender.decrease_allowance(accounts.bob, 200_000), Ok(()));
+            assert_eq!(meta_defender.allowance(accounts.eve, accounts.bob), 200_000);
+
+            set_sender(accounts.bob);
+            assert_eq!(
+                meta_defender.transfer_from(accounts.eve, accounts.alice, 200_001, Vec::new()),
+                Err(PSP22Error::InsufficientAllowance)
+            );
+            assert_eq!(meta_defender.transfer_from(accounts.eve, accounts.alice, 150_000, Vec::new()), Ok(()));
+            assert_eq!(meta_defender.allowance(accounts.eve, accounts.bob), 50_000);
+            assert_eq!(meta_defender.balance_of(accounts.alice), 150_000);
+            assert_eq!(meta_defender.balance_of(accounts.eve), 850_000);
+            match last_event() {
+                Event::Approval(Approval { owner, spender, amount }) => {
+                    assert_eq!(owner, accounts.eve);
+                    assert_eq!(spender, accounts.bob);
+                    assert_eq!(amount, 50_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected an Approval event"),
+            }
+        }
+
+        #[ink::test]
+        fn try_policy_cancel_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            match last_event() {
+                Event::PolicyCancelled(PolicyCancelled { policy_id, caller, deposit, recipient, bounty }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(caller, accounts.frank);
+                    assert_eq!(deposit, 10);
+                    assert_eq!(recipient, accounts.frank);
+                    assert_eq!(bounty, 0);
+                }
+                _ => panic!("encountered unexpected event kind: expected a PolicyCancelled event"),
+            }
+        }
+
+        #[ink::test]
+        fn policy_claim_apply_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            match last_event() {
+                Event::ClaimApplied(ClaimApplied { policy_id, beneficiary, .. }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimApplied event"),
+            }
+        }
+
+        #[ink::test]
+        fn refuse_apply_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Ok(()));
+            match last_event() {
+                Event::ClaimRefused(ClaimRefused { policy_id, beneficiary, .. }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimRefused event"),
+            }
+        }
+
+        #[ink::test]
+        fn accept_apply_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Ok(()));
+            match last_event() {
+                Event::ClaimAccepted(ClaimAccepted { policy_id, beneficiary, amount }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
+                    assert_eq!(amount, 10_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimAccepted event"),
+            }
+            assert_eq!(mock_erc20::balance_of(accounts.django), 90_000);
+        }
+
+        #[ink::test]
+        fn unused_capital_for_mining_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.is_valid_mining_proxy.insert(accounts.alice, &true);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.unused_capital_for_mining(500, accounts.alice), Ok(()));
+            match last_event() {
+                Event::MiningFundsSent(MiningFundsSent { proxy, amount }) => {
+                    assert_eq!(proxy, accounts.alice);
+                    assert_eq!(amount, 500);
+                }
+                _ => panic!("encountered unexpected event kind: expected a MiningFundsSent event"),
+            }
+        }
+
+        #[ink::test]
+        fn team_claim_emits_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.team_claim(), Ok(()));
+            match last_event() {
+                Event::TeamRewardClaimed(TeamRewardClaimed { official, amount }) => {
+                    assert_eq!(official, accounts.bob);
+                    assert_eq!(amount, 10);
+                }
+                _ => panic!("encountered unexpected event kind: expected a TeamRewardClaimed event"),
+            }
+        }
+
+        #[ink::test]
+        fn token_pulls_need_an_allowance() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            mock_erc20::approve(accounts.eve, contract_id(), 999_999);
+            mock_erc20::approve(accounts.frank, contract_id(), 0);
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Err(Error::InsufficientAllowance));
+            assert_eq!(meta_defender.provide_capital(999_999, Tranche::Junior), Ok(()));
+            assert_eq!(mock_erc20::allowance(accounts.eve, contract_id()), 0);
+
+            // the payer of a cover bought for someone else is charged, not the beneficiary
+            set_sender(accounts.frank);
+            assert_eq!(
+                meta_defender.buy_cover_for(accounts.alice, 10_000, 90, Balance::MAX, Timestamp::MAX),
+                Err(Error::InsufficientAllowance)
+            );
+            let total = meta_defender.get_quote(10_000, 90).unwrap().total;
+            mock_erc20::approve(accounts.frank, contract_id(), total);
+            assert_eq!(meta_defender.buy_cover_for(accounts.alice, 10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), 1_000_000 - total);
+
+            // claims are paid out of the reserve's allowance
+            mock_erc20::mint(accounts.django, 100_000);
+            mock_erc20::approve(accounts.django, contract_id(), 0);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Err(Error::InsufficientAllowance));
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 0);
+        }
+
+        #[ink::test]
+        fn failed_messages_emit_no_event() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Err(Error::InsufficientBalance));
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Err(Error::NotJudger));
+            assert_eq!(meta_defender.team_claim(), Err(Error::NotOfficial));
+            assert_eq!(ink::env::test::recorded_events().count(), 0);
+        }
+
+        #[ink::test]
+        fn constructor_grants_roles() {
+            let accounts = default_accounts();
+            let meta_defender = create_funded();
+
+            assert!(meta_defender.has_role(Role::Official, accounts.bob));
+            assert!(meta_defender.has_role(Role::PauseAdmin, accounts.bob));
+            assert!(meta_defender.has_role(Role::ProxyManager, accounts.bob));
+            assert!(meta_defender.has_role(Role::Judger, accounts.charlie));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.bob));
+            assert!(!meta_defender.has_role(Role::Guardian, accounts.bob));
+        }
+
+        #[ink::test]
+        fn grant_and_revoke_role_works() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.grant_role(Role::Guardian, accounts.alice), Ok(()));
+            assert!(meta_defender.has_role(Role::Guardian, accounts.alice));
+            match last_event() {
+                Event::RoleGranted(RoleGranted { role, account, sender }) => {
+                    assert_eq!(role, Role::Guardian);
+                    assert_eq!(account, accounts.alice);
+                    assert_eq!(sender, accounts.bob);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleGranted event"),
+            }
+            // granting twice changes nothing
+            assert_eq!(meta_defender.grant_role(Role::Guardian, accounts.alice), Ok(()));
+            assert_eq!(ink::env::test::recorded_events().count(), 1);
+
+            assert_eq!(meta_defender.revoke_role(Role::Guardian, accounts.alice), Ok(()));
+            assert!(!meta_defender.has_role(Role::Guardian, accounts.alice));
+            match last_event() {
+                Event::RoleRevoked(RoleRevoked { role, account, sender }) => {
+                    assert_eq!(role, Role::Guardian);
+                    assert_eq!(account, accounts.alice);
+                    assert_eq!(sender, accounts.bob);
+                }
+                _ => panic!("encountered unexpected event kind: expected a RoleRevoked event"),
+            }
+            assert_eq!(meta_defender.revoke_role(Role::Guardian, accounts.alice), Ok(()));
+            assert_eq!(ink::env::test::recorded_events().count(), 2);
+        }
+
+        #[ink::test]
+        fn only_official_manages_roles() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.grant_role(Role::Judger, accounts.alice), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.revoke_role(Role::Official, accounts.bob), Err(Error::NotOfficial));
+            assert!(!meta_defender.has_role(Role::Judger, accounts.alice));
+            assert!(meta_defender.has_role(Role::Official, accounts.bob));
+        }
+
+        #[ink::test]
+        fn last_official_cannot_be_revoked() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.revoke_role(Role::Official, accounts.bob), Err(Error::LastOfficial));
+            assert_eq!(meta_defender.grant_role(Role::Official, accounts.alice), Ok(()));
+            assert_eq!(meta_defender.revoke_role(Role::Official, accounts.bob), Ok(()));
+            assert!(!meta_defender.has_role(Role::Official, accounts.bob));
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.revoke_role(Role::Official, accounts.alice), Err(Error::LastOfficial));
+        }
+
+        #[ink::test]
+        fn granted_judger_can_judge_until_revoked() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.grant_role(Role::Judger, accounts.alice), Ok(()));
+            assert_eq!(meta_defender.revoke_role(Role::Judger, accounts.charlie), Ok(()));
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Err(Error::NotJudger));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Ok(()));
+        }
+
+        #[ink::test]
+        fn proxy_manager_manages_mining_proxies() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.valid_mining_proxy_manage(accounts.alice, true), Err(Error::NotProxyManager));
+            assert_eq!(meta_defender.is_valid_mining_proxy.get(accounts.alice), None);
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.valid_mining_proxy_manage(accounts.alice, true), Ok(()));
+            assert_eq!(meta_defender.is_valid_mining_proxy.get(accounts.alice), Some(true));
+            assert_eq!(meta_defender.valid_mining_proxy_manage(accounts.alice, false), Ok(()));
+            assert_eq!(meta_defender.is_valid_mining_proxy.get(accounts.alice), Some(false));
+        }
+
+        #[ink::test]
+        fn team_claim_only_pays_once() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.team_claim(), Ok(()));
+            assert_eq!(meta_defender.team_claim(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.bob), 10);
+            assert_eq!(meta_defender.claimable_team_reward, 0);
+        }
+
+        /// Asserts that the stored policy is listed for its beneficiary.
+        fn stored_policy(meta_defender: &MetaDefender, id: u128) -> PolicyInfo {
+            let policy = meta_defender.policies.get(id).expect("policy is not stored");
+            let count = meta_defender.get_user_policy_count(policy.beneficiary);
+            assert!(
+                (0..count).any(|n| meta_defender.get_user_policy_id(policy.beneficiary, n) == Some(id)),
+                "policy is not in the user policies"
+            );
+            policy
+        }
+
+        #[ink::test]
+        fn buy_cover_stores_every_user_policy() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(5_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+
+            assert_eq!(meta_defender.get_user_policy_count(accounts.frank), 2);
+            assert_eq!(meta_defender.get_user_policy_id(accounts.frank, 0), Some(0));
+            assert_eq!(meta_defender.get_user_policy_id(accounts.frank, 1), Some(1));
+            assert_eq!(meta_defender.get_user_policy_id(accounts.frank, 2), None);
+            assert_eq!(stored_policy(&meta_defender, 1).coverage, 5_000);
+        }
+
+        #[ink::test]
+        fn many_covers_for_one_beneficiary_keep_their_policies_writable() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            // anyone can buy covers for anyone, each one is listed apart
+            mock_erc20::mint(accounts.alice, 1_000_000);
+            set_sender(accounts.alice);
+            for _ in 0..100 {
+                assert_eq!(meta_defender.buy_cover_for(accounts.frank, 2, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            }
+            assert_eq!(meta_defender.get_user_policy_count(accounts.frank), 101);
+            assert_eq!(meta_defender.get_user_policy_id(accounts.frank, 100), Some(100));
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+        }
+
+        #[ink::test]
+        fn claim_apply_then_accept_is_stored() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            let policy = stored_policy(&meta_defender, 0);
+            assert!(policy.in_claim_applying);
+            assert!(!policy.is_claimed);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Ok(()));
+            let policy = stored_policy(&meta_defender, 0);
+            assert!(!policy.in_claim_applying);
+            assert!(policy.is_claimed);
+
+            // the same policy can be neither accepted nor claimed twice
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Err(Error::NotInClaimingProgress));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Err(Error::AlreadyClaimedPolicy));
+        }
+
+        #[ink::test]
+        fn claim_apply_refuse_and_reapply_is_stored() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Err(Error::InClaimingProgress));
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Ok(()));
+            let policy = stored_policy(&meta_defender, 0);
+            assert!(!policy.in_claim_applying);
+            assert!(!policy.is_claimed);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Err(Error::NotInClaimingProgress));
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+        }
+
+        #[ink::test]
+        fn claim_records_evidence_and_decision() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+            let evidence = Hash::from([7; 32]);
+            assert_eq!(meta_defender.get_claim(0), None);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, evidence, 0), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.policy_claim_apply(0, evidence, 10_001), Err(Error::InvalidClaimAmount));
+            set_block_timestamp(1_000);
+            assert_eq!(meta_defender.policy_claim_apply(0, evidence, 4_000), Ok(()));
+            assert_eq!(meta_defender.get_claim(0), Some(Claim {
+                policy_id: 0,
+                evidence,
+                amount: 4_000,
+                submitted_at: 1_000,
+                status: ClaimStatus::Pending,
+                reason: 0,
+                decided_at: None,
+                deadline: 1_000 + CLAIM_DEADLINE,
+                appealed: false,
+                round: 0,
+                approvals: 0,
+                rejections: 0,
+                approved_amount: 0,
+                challenge_ends: None,
+                objections: 0,
+            }));
+
+            set_block_timestamp(2_000);
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 3), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Refused);
+            assert_eq!(claim.reason, 3);
+            assert_eq!(claim.decided_at, Some(2_000));
+            match last_event() {
+                Event::ClaimRefused(ClaimRefused { reason, .. }) => assert_eq!(reason, 3),
+                _ => panic!("encountered unexpected event kind: expected a ClaimRefused event"),
+            }
+
+            // only the amount asked for is paid
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, evidence, 4_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 4_000), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.django), 96_000);
+            assert_eq!(meta_defender.get_claim(0).unwrap().status, ClaimStatus::Accepted);
+        }
+
+        #[ink::test]
+        fn partial_claims_are_paid_until_coverage_is_used_up() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+            let delta_acc_sps = stored_policy(&meta_defender, 0).delta_acc_sps;
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 6_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 0), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.accept_apply(0, 6_001), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.accept_apply(0, 2_500), Ok(()));
+            let policy = stored_policy(&meta_defender, 0);
+            assert_eq!(policy.claimed_amount, 2_500);
+            assert!(!policy.is_claimed);
+            assert_eq!(policy.delta_acc_sps, delta_acc_sps - delta_acc_sps / 4);
+            assert_eq!(meta_defender.junior.acc_sps_down, delta_acc_sps / 4);
+            assert_eq!(meta_defender.total_coverage, 7_500);
+            assert_eq!(mock_erc20::balance_of(accounts.django), 97_500);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 7_501), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 7_500), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 7_500), Ok(()));
+            let policy = stored_policy(&meta_defender, 0);
+            assert_eq!(policy.claimed_amount, 10_000);
+            assert!(policy.is_claimed);
+            assert_eq!(policy.delta_acc_sps, 0);
+            assert_eq!(meta_defender.junior.acc_sps_down, delta_acc_sps);
+            assert_eq!(meta_defender.total_coverage, 0);
+            assert_eq!(mock_erc20::balance_of(accounts.django), 90_000);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 1), Err(Error::AlreadyClaimedPolicy));
+        }
+
+        #[ink::test]
+        fn partially_claimed_policy_retires_its_remaining_coverage() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(5_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 4_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 4_000), Ok(()));
+            assert_eq!(meta_defender.total_coverage, 11_000);
+
+            // the renewal covers what is left
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(stored_policy(&meta_defender, 2).coverage, 6_000);
+            assert_eq!(meta_defender.total_coverage, 11_000);
+
+            assert_eq!(meta_defender.try_policy_cancel(1), Ok(()));
+            assert_eq!(meta_defender.total_coverage, 6_000);
+        }
+
+        fn create_with_committee() -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.grant_role(Role::Judger, accounts.alice), Ok(()));
+            assert_eq!(meta_defender.grant_role(Role::Judger, accounts.eve), Ok(()));
+            assert_eq!(meta_defender.set_vote_threshold(2), Ok(()));
+            meta_defender
+        }
+
+        #[ink::test]
+        fn vote_threshold_is_set_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            assert_eq!(meta_defender.get_vote_threshold(), 1);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.set_vote_threshold(1), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_vote_threshold(0), Err(Error::InvalidThreshold));
+            assert_eq!(meta_defender.set_vote_threshold(2), Err(Error::InvalidThreshold));
+            assert_eq!(meta_defender.grant_role(Role::Judger, accounts.alice), Ok(()));
+            assert_eq!(meta_defender.set_vote_threshold(2), Ok(()));
+            assert_eq!(meta_defender.get_vote_threshold(), 2);
+            match last_event() {
+                Event::VoteThresholdSet(VoteThresholdSet { threshold }) => assert_eq!(threshold, 2),
+                _ => panic!("encountered unexpected event kind: expected a VoteThresholdSet event"),
             }
+        }
+
+        #[ink::test]
+        fn judgers_are_not_brought_below_the_vote_threshold() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_committee();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.revoke_role(Role::Judger, accounts.eve), Ok(()));
+            assert_eq!(meta_defender.revoke_role(Role::Judger, accounts.alice), Err(Error::InvalidThreshold));
+
+            // handing the role to another judger would leave one judger
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.propose_judger(accounts.alice, None), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.accept_judger(), Err(Error::InvalidThreshold));
+            assert!(meta_defender.has_role(Role::Judger, accounts.charlie));
 
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_vote_threshold(1), Ok(()));
+            assert_eq!(meta_defender.revoke_role(Role::Judger, accounts.alice), Ok(()));
         }
 
+        #[ink::test]
+        fn committee_pays_a_claim_once_the_threshold_approves() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_committee();
+            mock_erc20::mint(accounts.django, 100_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 3_000), Ok(()));
+            assert_eq!(meta_defender.vote_claim(0, true, 3_000), Err(Error::NotJudger));
+
+            // no single judger decides
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 3_000), Err(Error::CommitteeVoteRequired));
+            assert_eq!(meta_defender.refuse_apply(0, 1), Err(Error::CommitteeVoteRequired));
+            assert_eq!(meta_defender.vote_claim(0, true, 0), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.vote_claim(0, true, 3_001), Err(Error::InvalidClaimAmount));
+            assert_eq!(meta_defender.vote_claim(0, true, 3_000), Ok(()));
+            assert_eq!(meta_defender.vote_claim(0, false, 0), Err(Error::AlreadyVoted));
+            match last_event() {
+                Event::ClaimVoted(ClaimVoted { policy_id, voter, approve, approved_amount, approvals, rejections }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(voter, accounts.charlie);
+                    assert!(approve);
+                    assert_eq!(approved_amount, 3_000);
+                    assert_eq!(approvals, 1);
+                    assert_eq!(rejections, 0);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimVoted event"),
+            }
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.vote_claim(0, false, 0), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+            assert_eq!(mock_erc20::balance_of(accounts.django), 100_000);
 
-        /// This message can calculate the current premium rate
-        /// 
-        /// If current useable capital is zero, return 0
-        #[ink(message)]
-        pub fn get_fee(&self)  -> Balance {
-            let useable_capital = self.get_useable_capital();
-            if useable_capital != 0 {
-                let fee = self.k_last/(useable_capital + self.virtual_param);
-                fee
-            }else{
-                0
+            // the claim pays the smallest amount approved
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.vote_claim(0, true, 2_500), Ok(()));
+            assert_eq!(stored_policy(&meta_defender, 0).claimed_amount, 2_500);
+            assert_eq!(meta_defender.get_claim(0).unwrap().status, ClaimStatus::Accepted);
+            assert_eq!(mock_erc20::balance_of(accounts.django), 97_500);
+            match last_event() {
+                Event::ClaimAccepted(ClaimAccepted { amount, .. }) => assert_eq!(amount, 2_500),
+                _ => panic!("encountered unexpected event kind: expected a ClaimAccepted event"),
             }
         }
-    
-        /// User buys a cover for himself with the specific coverage
-        #[ink(message)]
-        pub fn buy_cover(&mut self, coverage: Balance)  -> Result<()> {
-            
-            let useable_capital = self.get_useable_capital();
-            if useable_capital == 0 || coverage > useable_capital*2/100 {
-                Err(Error::InsufficientCoverage)
-            } else {
-                let beneficiary = self.env().caller();
-                let fee = self.get_fee();
-                let cover_fee = coverage * fee / 100_000;
-                let deposit = cover_fee * 5 / 100;
-                let total_pay = cover_fee + deposit;
-                let this = self.env().account_id();
-
-                match self.erc20.transfer_from(beneficiary, this, total_pay){
-                    Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                    Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                    Err(_e) => return Err(Error::TransferError),
-                    Ok(_) => return {
-                        self.total_coverage += coverage;
-                        let delta_acc_sps = coverage * 10_000_000_000_000 / self.stoken_supply;
-                        self.acc_sps += delta_acc_sps;
-        
-        
-                        // 5% goes to the team, remaining goes to underwriters
-                        let reward_for_team = cover_fee * 5 / 100;
-                        self.claimable_team_reward += reward_for_team;
-                        let reward_for_providers = cover_fee - reward_for_team;
-                        let delta_acc_rps = reward_for_providers * 10_000_000_000_000 / self.stoken_supply;
-                        self.acc_rps += delta_acc_rps;
-        
-                        let start_time = self.env().block_timestamp();
-                        let effective_until = start_time + 90 * 86_400_000;
-        
-                        
-                        let latest_provider_index = self.provider_count;
-                        
-                        let policy = PolicyInfo{
-                            id: self.policy_count.clone(),
-                            beneficiary,
-                            coverage,
-                            deposit,
-                            start_time,
-                            effective_until,
-                            latest_provider_index,
-                            delta_acc_sps,
-                            is_claimed: false,
-                            in_claim_applying: false,
-                            is_canceled: false,
-                        };
-        
-                        self.policies.insert(&self.policy_count, &policy);
-        
-        
-                        match self.user_policies.get(beneficiary) {
-                            Some(mut v) => v.push(policy),
-                            None => {
-                                self.user_policies.insert(&beneficiary, &Vec::from([policy]));
-                            }
-                        }
-        
-                        self.policy_count += 1;
-        
-                        Ok(())
-                    }
-                    }
-                }
 
+        #[ink::test]
+        fn committee_votes_start_over_on_appeal() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_committee();
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 3_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.vote_claim(0, false, 0), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.vote_claim(0, false, 0), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Refused);
+            assert_eq!(claim.rejections, 2);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.round, 1);
+            assert_eq!(claim.rejections, 0);
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.vote_claim(0, true, 3_000), Ok(()));
+            assert_eq!(meta_defender.get_claim(0).unwrap().approvals, 1);
+        }
 
+        fn create_with_large_claim() -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_challenge_params(500, 50_000), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 8_000), Ok(()));
+            meta_defender
+        }
 
+        #[ink::test]
+        fn challenge_params_are_set_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            assert_eq!(meta_defender.get_challenge_params(), (10_000, 50_000));
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.set_challenge_params(500, 50_000), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_challenge_params(Rate::ONE + 1, 50_000), Err(Error::InvalidChallengeParams));
+            assert_eq!(meta_defender.set_challenge_params(500, 0), Err(Error::InvalidChallengeParams));
+            assert_eq!(meta_defender.set_challenge_params(500, Rate::ONE + 1), Err(Error::InvalidChallengeParams));
+            assert_eq!(meta_defender.set_challenge_params(500, 30_000), Ok(()));
+            assert_eq!(meta_defender.get_challenge_params(), (500, 30_000));
+            match last_event() {
+                Event::ChallengeParamsSet(ChallengeParamsSet { large_claim_share, veto_share }) => {
+                    assert_eq!(large_claim_share, 500);
+                    assert_eq!(veto_share, 30_000);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ChallengeParamsSet event"),
+            }
         }
 
-        
-        #[ink(message)]
-        pub fn provide_capital(&mut self, amount: Balance)  -> Result<()> {
-            let provider = self.env().caller();
-            let this = self.env().account_id();
-            if let Some(_v) = self.provider_map.get(provider){
-                return Err(Error::ExistingUnderWriter);
+        #[ink::test]
+        fn large_claim_is_paid_after_an_unopposed_challenge() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_large_claim();
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Challenged);
+            assert_eq!(claim.approved_amount, 8_000);
+            assert_eq!(claim.challenge_ends, Some(CHALLENGE_PERIOD));
+            assert_eq!(mock_erc20::balance_of(accounts.django), 100_000);
+            match last_event() {
+                Event::ClaimChallengeOpened(ClaimChallengeOpened { policy_id, amount, ends_at }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(amount, 8_000);
+                    assert_eq!(ends_at, CHALLENGE_PERIOD);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimChallengeOpened event"),
             }
 
-            match self.erc20.transfer_from(provider, this , amount) {
-                Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                Err(_e) => return Err(Error::TransferError),
-                Ok(_) => {
-                            let index = self.provider_count.clone(); 
-                            let participation_time = self.env().block_timestamp();
-                            let stoken_amount = amount * 100_000 / self.exchange_rate;
-                            let rdebt = stoken_amount * self.acc_rps / 10_000_000_000_000;
-                            let sdebt = stoken_amount * self.acc_sps / 10_000_000_000_000;
-                            
-                            self.stoken_supply += stoken_amount;
+            // the judger can't take it back in the meantime
+            assert_eq!(meta_defender.refuse_apply(0, 1), Err(Error::ClaimChallenged));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.execute_claim(0), Err(Error::ChallengePeriodNotOver));
+            set_block_timestamp(CHALLENGE_PERIOD + 1);
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.object_claim(0), Err(Error::ChallengePeriodOver));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.execute_claim(0), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.django), 92_000);
+            assert_eq!(stored_policy(&meta_defender, 0).claimed_amount, 8_000);
+            assert_eq!(meta_defender.execute_claim(0), Err(Error::NotChallengedClaim));
+        }
+
+        #[ink::test]
+        fn underwriters_veto_a_large_claim_into_a_second_review() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_large_claim();
 
-                            let provider_info = ProviderInfo{
-                                index,
-                                participation_time,
-                                stoken_amount,
-                                rdebt,
-                                sdebt,
-                            };
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.object_claim(0), Err(Error::NotUnderwriter));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.object_claim(0), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Escalated);
+            assert_eq!(claim.challenge_ends, None);
+            assert_eq!(meta_defender.object_claim(0), Err(Error::NotChallengedClaim));
+            match last_event() {
+                Event::ClaimEscalated(ClaimEscalated { policy_id, beneficiary }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
+                }
+                _ => panic!("encountered unexpected event kind: expected a ClaimEscalated event"),
+            }
 
-                            self.provider_map.insert(provider, &provider_info);
+            // the officials decide for good
+            set_block_timestamp(CHALLENGE_PERIOD + 1);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.execute_claim(0), Err(Error::NotChallengedClaim));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.accept_apply(0, 6_000), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.django), 94_000);
+            assert_eq!(meta_defender.get_claim(0).unwrap().status, ClaimStatus::Accepted);
+        }
 
-                            let pre_useable_capital = self.get_useable_capital().clone();
-                            self.token_staked_here += amount;
-                            let current_useable_capital = self.get_useable_capital();
+        #[ink::test]
+        fn objecting_stoken_stay_put_until_the_challenge_is_over() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.alice, 700_000, Vec::new()), Ok(()));
+            mock_erc20::mint(accounts.django, 100_000);
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_challenge_params(500, 50_000), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 8_000), Ok(()));
+
+            // eve holds 30% of the stoken and can't count them twice through another account
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.object_claim(0), Ok(()));
+            assert_eq!(
+                meta_defender.transfer(accounts.django, 300_000, Vec::new()),
+                Err(PSP22Error::Custom(String::from("StokenInObjection")))
+            );
+            assert_eq!(meta_defender.decrease_capital(1_000), Err(Error::StokenInObjection));
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::StokenInObjection));
+            assert_eq!(meta_defender.get_claim(0).unwrap().status, ClaimStatus::Challenged);
 
-                            self.update_k_last_by_provider(pre_useable_capital, current_useable_capital); //更新kLast
-                            self.provider_count += 1;
+            // others can still move theirs, and eve once the challenge is over
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.transfer(accounts.django, 1_000, Vec::new()), Ok(()));
+            set_block_timestamp(CHALLENGE_PERIOD + 1);
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.django, 300_000, Vec::new()), Ok(()));
+        }
 
-                            Ok(())
+        #[ink::test]
+        fn refused_claim_is_appealed_once_within_the_window() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::NotRefusedClaim));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::NotRefusedClaim));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 1), Ok(()));
+
+            set_block_timestamp(APPEAL_WINDOW);
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::NotBeneficiary));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Appealed);
+            assert_eq!(claim.deadline, APPEAL_WINDOW + CLAIM_DEADLINE);
+            match last_event() {
+                Event::ClaimAppealed(ClaimAppealed { policy_id, beneficiary }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
                 }
+                _ => panic!("encountered unexpected event kind: expected a ClaimAppealed event"),
             }
 
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 2), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::AlreadyAppealed));
 
+            // with the appeal spent, filing again opens a new claim
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Pending);
+            assert!(!claim.appealed);
         }
 
+        #[ink::test]
+        fn refused_claim_is_filed_again_in_place_of_its_appeal() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 1), Ok(()));
+            set_block_timestamp(APPEAL_WINDOW);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 5_000), Ok(()));
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.amount, 5_000);
+            assert!(claim.appealed);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 1), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::AlreadyAppealed));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 5_000), Ok(()));
+            assert!(!meta_defender.get_claim(0).unwrap().appealed);
+        }
 
-        fn update_k_last_by_provider(&mut self, pre_useable_capital: Balance, current_useable_capital: Balance) {
-            if self.provider_count == 0 {
-                self.k_last = self.initial_fee * (current_useable_capital + self.virtual_param); 
-            }else{
-                let fee = self.k_last / (pre_useable_capital + self.virtual_param);
-                self.k_last = fee * (current_useable_capital + self.virtual_param);
-            }
+        #[ink::test]
+        fn refused_claim_is_filed_again_as_a_new_claim_past_the_appeal_window() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 1), Ok(()));
+            set_block_timestamp(APPEAL_WINDOW + 1);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.appeal_claim(0), Err(Error::AppealWindowClosed));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+            let claim = meta_defender.get_claim(0).unwrap();
+            assert_eq!(claim.status, ClaimStatus::Pending);
+            assert!(!claim.appealed);
         }
 
+        #[ink::test]
+        fn undecided_claim_is_escalated_to_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            mock_erc20::mint(accounts.django, 100_000);
 
-        fn get_reward(&self, address: &AccountId) -> Balance {
-            match self.provider_map.get(address){
-                None =>  0,
-                Some(v) => {
-                    if v.stoken_amount != 0 {
-                        v.stoken_amount * self.acc_rps / 10_000_000_000_000 - v.rdebt
-                    }else{
-                        0
-                    }
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.escalate_claim(0), Err(Error::NotInClaimingProgress));
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            set_block_timestamp(CLAIM_DEADLINE);
+            assert_eq!(meta_defender.escalate_claim(0), Err(Error::ClaimDeadlineNotReached));
+
+            set_block_timestamp(CLAIM_DEADLINE + 1);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.escalate_claim(0), Err(Error::NotBeneficiary));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.escalate_claim(0), Ok(()));
+            assert_eq!(meta_defender.escalate_claim(0), Err(Error::AlreadyEscalated));
+            assert_eq!(meta_defender.get_claim(0).unwrap().status, ClaimStatus::Escalated);
+            match last_event() {
+                Event::ClaimEscalated(ClaimEscalated { policy_id, beneficiary }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(beneficiary, accounts.frank);
                 }
+                _ => panic!("encountered unexpected event kind: expected a ClaimEscalated event"),
             }
+
+            // the judger steps aside
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 1), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).is_claimed);
         }
 
-        #[ink(message)]
-        pub fn provider_take_reward(&mut self) -> Result<()>{
-            let caller = self.env().caller();
-            match self.provider_map.get(caller) {
-                None => Err(Error::NotUnderwriter),
-                Some(v) if v.stoken_amount == 0 => Err(Error::NotValidUnderwriter),
-                Some(mut v) => {
-                    let reward = self.get_reward(&caller);
-                    v.rdebt = v.stoken_amount * self.acc_rps / 10_000_000_000_000;
-                    match self.erc20.transfer(caller, reward){
-                        Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                        Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                        Err(_e) => return Err(Error::TransferError),
-                        Ok(_) => return Ok(())
-                    }
-                }
-            }
+        #[ink::test]
+        fn policy_in_claim_cannot_be_cancelled() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+
+            set_block_timestamp(90 * 86_400_000);
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::ClaimingInProgress));
         }
 
-        fn get_shadow(&self, provider: &ProviderInfo) -> Balance{
-            if provider.index > self.latest_unfrozen_index{
-                provider.stoken_amount * self.acc_sps / 10_000_000_000_000 - provider.sdebt
-            }else{
-                let delta = self.acc_sps - self.acc_sps_down;
-                provider.stoken_amount * delta / 10_000_000_000_000
-            }
+        #[ink::test]
+        fn policy_cancel_is_stored() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            assert!(stored_policy(&meta_defender, 0).is_canceled);
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::AlreadyCancelledPolicy));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), 1_000_000 - 210 + 10);
+        }
+
+        #[ink::test]
+        fn provider_take_reward_only_pays_once() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 190);
+            assert_eq!(meta_defender.provider_map.get(accounts.eve).unwrap().rdebt, 190);
+
+            assert_eq!(meta_defender.provider_take_reward(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 190);
+
+            // a new premium is shared again
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Ok(()));
+            assert!(mock_erc20::balance_of(accounts.eve) > 190);
+        }
 
+        #[ink::test]
+        fn abolished_provider_is_stored() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(provider.stoken_amount, 0);
+            assert_eq!(provider.rdebt, 0);
+            assert_eq!(meta_defender.junior.stoken_supply, 0);
+
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::NotValidUnderwriter));
+            assert_eq!(meta_defender.provider_take_reward(), Err(Error::NotValidUnderwriter));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 990_190);
         }
 
-        fn get_shadow_historical_provider(&self, historical_provider: &HistoricalProviderInfo) -> Balance {
-            if historical_provider.index_before > self.latest_unfrozen_index{
-                historical_provider.stoken_amount_before * historical_provider.acc_sps_while_left / 10_000_000_000_000 - historical_provider.sdebt_before
-            }else{
-                if historical_provider.acc_sps_while_left >= self.acc_sps_down {
-                    historical_provider.acc_sps_while_left - self.acc_sps_down
-                } else{
-                    let delta = historical_provider.acc_sps_while_left - self.acc_sps_down;
-                    historical_provider.stoken_amount_before * delta / 10_000_000_000_000
+        #[ink::test]
+        fn abolished_provider_can_provide_again() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Err(Error::ExistingUnderWriter));
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 1_000_000);
+
+            assert_eq!(meta_defender.provide_capital(500_000, Tranche::Junior), Ok(()));
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(provider.index, 1);
+            assert_eq!(provider.stoken_amount, 500_000);
+            assert!(meta_defender.historical_provider_map.get(accounts.eve).is_none());
+        }
+
+        #[ink::test]
+        fn frozen_capital_must_be_withdrawn_before_providing_again() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Err(Error::HistoricalCapitalNotWithdrawn));
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            assert_eq!(meta_defender.historical_provider_map.get(accounts.eve).unwrap().ftoken, 0);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Err(Error::InsufficientSToken));
+
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Ok(()));
+            assert_eq!(meta_defender.provider_map.get(accounts.eve).unwrap().stoken_amount, 1_000);
+        }
+
+        #[ink::test]
+        fn guardian_pauses_but_cannot_unpause() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.grant_role(Role::Guardian, accounts.alice), Ok(()));
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.pause(PauseFlag::NewCovers), Ok(()));
+            assert!(meta_defender.is_paused(PauseFlag::NewCovers));
+            assert!(!meta_defender.is_paused(PauseFlag::Underwriting));
+            match last_event() {
+                Event::Paused(Paused { flag, account }) => {
+                    assert_eq!(flag, PauseFlag::NewCovers);
+                    assert_eq!(account, accounts.alice);
                 }
+                _ => panic!("encountered unexpected event kind: expected a Paused event"),
+            }
+            assert_eq!(meta_defender.unpause(PauseFlag::NewCovers), Err(Error::NotPauseAdmin));
+            assert!(meta_defender.is_paused(PauseFlag::NewCovers));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.unpause(PauseFlag::NewCovers), Ok(()));
+            assert!(!meta_defender.is_paused(PauseFlag::NewCovers));
+            match last_event() {
+                Event::Unpaused(Unpaused { flag, account }) => {
+                    assert_eq!(flag, PauseFlag::NewCovers);
+                    assert_eq!(account, accounts.bob);
+                }
+                _ => panic!("encountered unexpected event kind: expected an Unpaused event"),
             }
         }
 
+        #[ink::test]
+        fn only_guardian_or_pause_admin_pauses() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
 
-        fn register_historical_provider(&mut self, provider: &ProviderInfo, token_remain:Balance, withdrawable_capital:Balance, address: &AccountId){
-            let index_before = provider.index;
-            let stoken_amount_before = provider.stoken_amount;
-            let token_left = token_remain - withdrawable_capital;
-            let ftoken = token_left * 100_000 / self.exchange_rate;
-            let acc_sps_while_left = self.acc_sps;
-            let sdebt_before = provider.sdebt;
-            let historical_provider = HistoricalProviderInfo{
-                index_before, 
-                stoken_amount_before,
-                ftoken,
-                acc_sps_while_left, 
-                sdebt_before, 
-            };
-            self.token_frozen_here += token_left;
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.pause(PauseFlag::ClaimsPayout), Err(Error::NotGuardian));
+            assert_eq!(meta_defender.unpause(PauseFlag::ClaimsPayout), Err(Error::NotPauseAdmin));
+            assert!(!meta_defender.is_paused(PauseFlag::ClaimsPayout));
 
-            self.historical_provider_map.insert(address, &historical_provider);
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::ClaimsPayout), Ok(()));
+            assert!(meta_defender.is_paused(PauseFlag::ClaimsPayout));
         }
 
-        #[ink(message)]
-        pub fn provider_abolish(&mut self) -> Result<()> {
-            let caller = self.env().caller();
-            match self.provider_map.get(caller) {
-                None => Err(Error::NotUnderwriter),
-                Some(_v) if self.provider_leaving == true => Err(Error::ProviderLeavingInProgress),
-                Some(v) if v.stoken_amount == 0 => Err(Error::NotValidUnderwriter),
-                Some(mut v) => {
-                    self.provider_leaving = true;
+        #[ink::test]
+        fn paused_messages_are_rejected() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.is_valid_mining_proxy.insert(accounts.alice, &true);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::Underwriting), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Err(Error::Paused));
+            assert_eq!(meta_defender.increase_capital(1_000), Err(Error::Paused));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::NewCovers), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::Paused));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::ClaimsPayout), Ok(()));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Err(Error::Paused));
+            assert_eq!(meta_defender.vote_claim(0, true, 10_000), Err(Error::Paused));
+            assert_eq!(meta_defender.execute_claim(0), Err(Error::Paused));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::Withdrawals), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Err(Error::Paused));
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::Paused));
+            assert_eq!(meta_defender.decrease_capital(1_000), Err(Error::Paused));
+            assert_eq!(meta_defender.historical_provider_withdraw(), Err(Error::Paused));
+            assert_eq!(
+                meta_defender.transfer(accounts.alice, 1_000, Vec::new()),
+                Err(PSP22Error::Custom(String::from("Paused")))
+            );
+            assert_eq!(meta_defender.approve(accounts.bob, 1_000), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(
+                meta_defender.transfer_from(accounts.eve, accounts.alice, 1_000, Vec::new()),
+                Err(PSP22Error::Custom(String::from("Paused")))
+            );
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.unused_capital_for_mining(1, accounts.alice), Err(Error::Paused));
 
-                    let token_remain = v.stoken_amount * self.exchange_rate / 100_000;
-                    let shadow = self.get_shadow(&v);
+            // nothing moved while paused
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 0);
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 0);
+            assert!(stored_policy(&meta_defender, 0).in_claim_applying);
+        }
 
-                    let withdrawable_capital: u128;
-                    if token_remain >= shadow {
-                        withdrawable_capital = token_remain - shadow;
-                    } else{
-                        withdrawable_capital = 0;
-                    }
+        #[ink::test]
+        fn pausing_is_per_function() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.pause(PauseFlag::NewCovers), Ok(()));
+            assert_eq!(meta_defender.pause(PauseFlag::ClaimsPayout), Ok(()));
+
+            // underwriters can still add capital and leave
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Ok(()));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.unpause(PauseFlag::NewCovers), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+        }
 
-                    let reward = v.stoken_amount * self.acc_rps / 10_000_000_000_000 - v.rdebt;
+        #[ink::test]
+        fn only_official_upgrades_and_migrates() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            assert_eq!(meta_defender.get_storage_version(), STORAGE_VERSION);
+
+            // the upgrade itself needs a chain, see `e2e_tests::upgrade_keeps_storage`
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.upgrade([0x01; 32]), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.migrate(Vec::new(), Vec::new()), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.finish_migration(), Err(Error::NotOfficial));
+        }
 
-                    self.register_historical_provider(&v, token_remain, withdrawable_capital, &caller);
+        #[ink::test]
+        fn migration_of_current_layout_is_a_no_op() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(vec![accounts.eve], vec![0]), Ok(()));
+            assert_eq!(meta_defender.finish_migration(), Ok(()));
+            assert_eq!(meta_defender.get_storage_version(), STORAGE_VERSION);
+            assert_eq!(stored_policy(&meta_defender, 0).coverage, 10_000);
+            // the layout is unchanged, no Migrated event
+            assert!(decoded_events().iter().all(|event| !matches!(event, Event::Migrated(_))));
+        }
 
-                    self.stoken_supply -= v.stoken_amount;
-                    v.stoken_amount = 0;
-                    v.rdebt = 0;
+        #[ink::test]
+        fn migration_from_unknown_layout_fails() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.storage_version = 0;
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(vec![accounts.eve], Vec::new()), Err(Error::UnknownStorageVersion));
+            assert_eq!(meta_defender.migrate(Vec::new(), vec![0]), Err(Error::UnknownStorageVersion));
+            // nothing to translate, the version is recorded
+            assert_eq!(meta_defender.migrate(Vec::new(), Vec::new()), Ok(()));
+            assert_eq!(meta_defender.finish_migration(), Ok(()));
+            match last_event() {
+                Event::Migrated(Migrated { from, to }) => {
+                    assert_eq!(from, 0);
+                    assert_eq!(to, STORAGE_VERSION);
+                }
+                _ => panic!("encountered unexpected event kind: expected a Migrated event"),
+            }
+        }
 
-                    let pre_useable_capital = self.get_useable_capital().clone();
-                    self.token_staked_here -= token_remain;
-                    let current_useable_capital = self.get_useable_capital();
-                    self.update_k_last_by_provider(pre_useable_capital, current_useable_capital);
+        #[ink::test]
+        fn migration_from_v1_adds_the_duration() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let policy = stored_policy(&meta_defender, 0);
+            let v1 = PolicyInfoV1 {
+                id: policy.id,
+                beneficiary: policy.beneficiary,
+                coverage: policy.coverage,
+                deposit: policy.deposit,
+                start_time: policy.start_time,
+                effective_until: policy.effective_until,
+                latest_provider_index: policy.latest_provider_index,
+                delta_acc_sps: policy.delta_acc_sps,
+                is_claimed: policy.is_claimed,
+                in_claim_applying: policy.in_claim_applying,
+                is_canceled: policy.is_canceled,
+            };
+            ink::env::set_contract_storage(&(meta_defender.policies.key(), 0u128), &v1);
+            ink::env::set_contract_storage(&(meta_defender.user_policies.key(), accounts.frank), &vec![v1]);
+            meta_defender.user_policies.remove((accounts.frank, 0));
+            meta_defender.user_policy_count.remove(accounts.frank);
+            meta_defender.storage_version = 1;
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(vec![accounts.frank], vec![0]), Ok(()));
+            assert_eq!(meta_defender.finish_migration(), Ok(()));
+            assert_eq!(meta_defender.get_storage_version(), 2);
+
+            let migrated = stored_policy(&meta_defender, 0);
+            assert_eq!(migrated.duration, 90);
+            assert_eq!(migrated.payer, accounts.frank);
+            assert_eq!(migrated.coverage, policy.coverage);
+            assert_eq!(migrated.effective_until, policy.effective_until);
+            assert_eq!(migrated.delta_acc_sps, policy.delta_acc_sps);
+            assert_eq!(meta_defender.get_user_policy_count(accounts.frank), 1);
+            assert_eq!(meta_defender.get_user_policy_id(accounts.frank, 0), Some(0));
+        }
 
-                    
-                    if withdrawable_capital + reward > 0 {
-                        match self.erc20.transfer(caller, withdrawable_capital + reward) {
-                            Err(e) if e == Erc20Error::InsufficientAllowance => {
-                                self.provider_leaving = false;
-                                return Err(Error::InsufficientAllowance);
-                            },
-                            Err(e) if e == Erc20Error::InsufficientBalance => {
-                                self.provider_leaving = false;
-                                return Err(Error::InsufficientBalance)
-                            },
-                            Err(_e) => {
-                                self.provider_leaving = false;
-                                return Err(Error::TransferError)},
-                            Ok(_) => {
-                                self.provider_leaving = false;
-                                return Ok(())
-                            }
-                        }
-                    }
-                self.provider_leaving = false;
-                Ok(())
-                }
+        /// A root of storage version 1 as written by its constructor, with its official roles still in storage.
+        fn root_v1() -> RootV1 {
+            let accounts = default_accounts();
+            RootV1 {
+                provider_count: 0,
+                exchange_rate: Rate::ONE,
+                policy_count: 0,
+                acc_rps: 0,
+                acc_sps: 0,
+                acc_sps_down: 0,
+                token_staked_here: 0,
+                stoken_supply: 0,
+                token_frozen_here: 0,
+                total_coverage: 0,
+                k_last: 0,
+                latest_unfrozen_index: 0,
+                initial_fee: 2_000,
+                min_fee: 2_000,
+                claimable_team_reward: 0,
+                virtual_param: 10_000_000,
+                locked: false,
+                storage_version: 1,
+                erc20: Erc20Ref::from_account_id(contract_id()),
+                risk_reserve: accounts.django,
             }
         }
 
-        #[ink(message)]
-        pub fn get_unfrozen_capital(&self) -> u128 {
-            let caller = self.env().caller();
-            if let Some(v) = self.historical_provider_map.get(caller) {
-                let shadow = self.get_shadow_historical_provider(&v);
-                if v.ftoken * self.exchange_rate / 100_000 <= shadow {
-                    return 0;
-                }else{
-                    return v.ftoken * self.exchange_rate / 100_000 - shadow;
-                }
+        #[ink::test]
+        fn root_of_v1_is_rewritten_by_officials() {
+            let accounts = default_accounts();
+            let _ = create_funded();
+            assert_eq!(MetaDefender::migrate_root(accounts.bob), Err(Error::MigrationFailed));
+
+            let mut root = root_v1();
+            root.storage_version = 0;
+            ink::env::set_contract_storage(&MetaDefender::KEY, &root);
+            assert_eq!(MetaDefender::migrate_root(accounts.bob), Err(Error::UnknownStorageVersion));
+
+            let mut root = root_v1();
+            root.policy_count = 2;
+            root.claimable_team_reward = 40;
+            ink::env::set_contract_storage(&MetaDefender::KEY, &root);
+            assert_eq!(MetaDefender::migrate_root(accounts.charlie), Err(Error::NotOfficial));
+            assert_eq!(MetaDefender::migrate_root(accounts.bob), Ok(()));
+
+            let mut migrated: MetaDefender = ink::env::get_contract_storage(&MetaDefender::KEY).unwrap().unwrap();
+            assert_eq!(migrated.get_storage_version(), 1);
+            assert_eq!(migrated.policy_count, 2);
+            assert_eq!(migrated.claimable_team_reward, 40);
+            assert_eq!(migrated.virtual_param, 10_000_000);
+            assert_eq!(migrated.risk_reserve, accounts.django);
+            assert_eq!(migrated.get_vote_threshold(), 1);
+            assert_eq!(migrated.policy_terms.get(90), Some(Rate::ONE));
+            assert!(migrated.has_role(Role::Official, accounts.bob));
+
+            set_sender(accounts.bob);
+            assert_eq!(migrated.finish_migration(), Ok(()));
+            assert_eq!(migrated.get_storage_version(), STORAGE_VERSION);
+        }
+
+        #[ink::test]
+        fn root_of_v1_moves_the_capital_to_the_junior_tranche() {
+            let accounts = default_accounts();
+            let _ = create_funded();
+            let mut root = root_v1();
+            root.exchange_rate = 90_000;
+            root.acc_rps = 7;
+            root.acc_sps = 11;
+            root.acc_sps_down = 3;
+            root.token_staked_here = 900_000;
+            root.stoken_supply = 1_000_000;
+            root.token_frozen_here = 4_000;
+            root.total_coverage = 10_000;
+            ink::env::set_contract_storage(&MetaDefender::KEY, &root);
+            assert_eq!(MetaDefender::migrate_root(accounts.bob), Ok(()));
+
+            let migrated: MetaDefender = ink::env::get_contract_storage(&MetaDefender::KEY).unwrap().unwrap();
+            assert_eq!(migrated.get_exchange_rate(Tranche::Junior), 90_000);
+            assert_eq!(migrated.junior.acc_rps, 7);
+            assert_eq!(migrated.junior.acc_sps, 11);
+            assert_eq!(migrated.junior.acc_sps_down, 3);
+            assert_eq!(migrated.junior.token_staked, 900_000);
+            assert_eq!(migrated.junior.stoken_supply, 1_000_000);
+            assert_eq!(migrated.junior.token_frozen, 4_000);
+            assert_eq!(migrated.total_supply(), 1_000_000);
+            assert_eq!(migrated.get_useable_capital(), 890_000);
+            // the senior tranche starts empty
+            assert_eq!(migrated.get_exchange_rate(Tranche::Senior), Rate::ONE);
+            assert_eq!(migrated.senior.token_staked, 0);
+            assert_eq!(migrated.senior.stoken_supply, 0);
+            assert_eq!(migrated.senior.acc_sps, 0);
+        }
+
+        #[ink::test]
+        fn migration_from_v1_translates_providers() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            let v1 = ProviderInfoV1 {
+                index: provider.index,
+                participation_time: provider.participation_time,
+                stoken_amount: provider.stoken_amount,
+                rdebt: provider.rdebt,
+                sdebt: provider.sdebt,
+            };
+            ink::env::set_contract_storage(&(meta_defender.provider_map.key(), accounts.eve), &v1);
+            let historical = HistoricalProviderInfoV1 {
+                index_before: 0,
+                stoken_amount_before: 1_000,
+                ftoken: 500,
+                acc_sps_while_left: 0,
+                sdebt_before: 0,
             };
+            ink::env::set_contract_storage(&(meta_defender.historical_provider_map.key(), accounts.eve), &historical);
+            meta_defender.storage_version = 1;
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(vec![accounts.eve], vec![]), Ok(()));
+            let migrated = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(migrated.stoken_amount, provider.stoken_amount);
+            assert_eq!(migrated.sdebt, provider.sdebt);
+            assert!(!migrated.auto_compound);
+            assert_eq!(migrated.tranche, Tranche::Junior);
+            let migrated = meta_defender.historical_provider_map.get(accounts.eve).unwrap();
+            assert_eq!(migrated.ftoken, 500);
+            assert_eq!(migrated.tranche, Tranche::Junior);
+            assert_eq!(meta_defender.get_reward(&accounts.eve), Ok(190));
+        }
 
-            match self.provider_map.get(caller) {
-                None => return 0,
-                Some(v) => {
-                    let token_remain = v.stoken_amount * self.exchange_rate / 100_000;
-                    if v.index > self.latest_unfrozen_index {
-                        let shadow = v.stoken_amount * self.acc_sps / 10_000_000_000_000 - v.sdebt;
-                        if token_remain >= shadow {
-                            return token_remain - shadow;
-                        }else{
-                            return 0;
-                        }
-                    }else{
-                        let delta = self.acc_sps - self.acc_sps_down;
-                        let shadow = v.stoken_amount * delta / 10_000_000_000_000;
-                        if token_remain >= shadow {
-                            return token_remain - shadow;
-                        }else{
-                            return 0;
-                        }
-                    }
+        #[ink::test]
+        fn migration_skips_records_passed_again() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let policy = stored_policy(&meta_defender, 0);
+            let provider = meta_defender.provider_map.get(accounts.eve).unwrap();
+            let v1 = PolicyInfoV1 {
+                id: policy.id,
+                beneficiary: policy.beneficiary,
+                coverage: policy.coverage,
+                deposit: policy.deposit,
+                start_time: policy.start_time,
+                effective_until: policy.effective_until,
+                latest_provider_index: policy.latest_provider_index,
+                delta_acc_sps: policy.delta_acc_sps,
+                is_claimed: policy.is_claimed,
+                in_claim_applying: policy.in_claim_applying,
+                is_canceled: policy.is_canceled,
+            };
+            ink::env::set_contract_storage(&(meta_defender.policies.key(), 0u128), &v1);
+            let v1 = ProviderInfoV1 {
+                index: provider.index,
+                participation_time: provider.participation_time,
+                stoken_amount: provider.stoken_amount,
+                rdebt: provider.rdebt,
+                sdebt: provider.sdebt,
+            };
+            ink::env::set_contract_storage(&(meta_defender.provider_map.key(), accounts.eve), &v1);
+            meta_defender.storage_version = 1;
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(vec![accounts.eve], vec![0]), Ok(()));
+            assert_eq!(meta_defender.migrate(vec![accounts.eve], vec![0]), Ok(()));
+            let migrated = stored_policy(&meta_defender, 0);
+            assert_eq!(migrated.payer, accounts.frank);
+            assert_eq!(migrated.coverage, policy.coverage);
+            assert_eq!(migrated.deposit, policy.deposit);
+            assert_eq!(migrated.effective_until, policy.effective_until);
+            let migrated = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(migrated.stoken_amount, provider.stoken_amount);
+            assert_eq!(migrated.sdebt, provider.sdebt);
+
+            // nothing changes the state before the migration is finished
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Err(Error::MigrationInProgress));
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::MigrationInProgress));
+            assert_eq!(meta_defender.cancel_expired(MAX_SWEEP), Err(Error::MigrationInProgress));
+            set_sender(accounts.eve);
+            assert_eq!(
+                meta_defender.transfer(accounts.django, 1_000, Vec::new()),
+                Err(PSP22Error::Custom(String::from("MigrationInProgress")))
+            );
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.finish_migration(), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.transfer(accounts.django, 1_000, Vec::new()), Ok(()));
+        }
+
+        #[ink::test]
+        fn migration_from_v1_counts_the_unfrozen_policies() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let policy = stored_policy(&meta_defender, 0);
+            let v1 = |id: u128, is_canceled: bool| PolicyInfoV1 {
+                id,
+                beneficiary: policy.beneficiary,
+                coverage: policy.coverage,
+                deposit: policy.deposit,
+                start_time: policy.start_time,
+                effective_until: policy.effective_until,
+                latest_provider_index: policy.latest_provider_index,
+                delta_acc_sps: policy.delta_acc_sps,
+                is_claimed: false,
+                in_claim_applying: false,
+                is_canceled,
+            };
+            for (id, is_canceled) in [(0u128, true), (1, true), (2, false)] {
+                ink::env::set_contract_storage(&(meta_defender.policies.key(), id), &v1(id, is_canceled));
+            }
+            meta_defender.storage_version = 1;
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.migrate(Vec::new(), vec![1, 2, 0]), Ok(()));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 2);
+        }
+
+        #[ink::test]
+        fn policy_terms_are_managed_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+            assert_eq!(meta_defender.get_policy_term(90), Some(Rate::ONE));
+            assert_eq!(meta_defender.get_policy_term(30), None);
+
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.set_policy_term(30, 40_000), Err(Error::NotOfficial));
+            assert_eq!(meta_defender.remove_policy_term(90), Err(Error::NotOfficial));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_policy_term(0, 40_000), Err(Error::InvalidPolicyTerm));
+            assert_eq!(meta_defender.set_policy_term(30, 0), Err(Error::InvalidPolicyTerm));
+            assert_eq!(meta_defender.set_policy_term(30, 40_000), Ok(()));
+            assert_eq!(meta_defender.get_policy_term(30), Some(40_000));
+            match last_event() {
+                Event::PolicyTermSet(PolicyTermSet { duration, multiplier }) => {
+                    assert_eq!(duration, 30);
+                    assert_eq!(multiplier, 40_000);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyTermSet event"),
+            }
+
+            assert_eq!(meta_defender.remove_policy_term(30), Ok(()));
+            assert_eq!(meta_defender.get_policy_term(30), None);
+            match last_event() {
+                Event::PolicyTermRemoved(PolicyTermRemoved { duration }) => assert_eq!(duration, 30),
+                _ => panic!("encountered unexpected event kind: expected a PolicyTermRemoved event"),
             }
+            assert_eq!(meta_defender.remove_policy_term(30), Err(Error::UnsupportedDuration));
+        }
+
+        #[ink::test]
+        fn buy_cover_applies_the_term_multiplier() {
+            let accounts = default_accounts();
+            set_block_timestamp(1_000);
+            let mut meta_defender = create_funded();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_policy_term(365, 300_000), Ok(()));
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+
+            // 3 times the 2% premium of a 90 days cover
+            let quote = meta_defender.get_quote(10_000, 365).unwrap();
+            assert_eq!(quote, Quote { cover_fee: 600, deposit: 30, total: 630 });
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 365, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), 1_000_000 - 630);
+            let policy = stored_policy(&meta_defender, 0);
+            assert_eq!(policy.duration, 365);
+            assert_eq!(policy.deposit, 30);
+            assert_eq!(policy.effective_until, 1_000 + 365 * 86_400_000);
+        }
+
+        #[ink::test]
+        fn get_quote_matches_what_buy_cover_charges() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            let quote = meta_defender.get_quote(7_777, 90).unwrap();
+            set_sender(accounts.frank);
+            let before = mock_erc20::balance_of(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(7_777, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(before - mock_erc20::balance_of(accounts.frank), quote.total);
+            assert_eq!(stored_policy(&meta_defender, 1).deposit, quote.deposit);
+        }
+
+        #[ink::test]
+        fn buy_cover_rejects_unlisted_durations() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            assert_eq!(meta_defender.get_quote(1_000, 45), Err(Error::UnsupportedDuration));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 45, Balance::MAX, Timestamp::MAX), Err(Error::UnsupportedDuration));
+
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.remove_policy_term(90), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::UnsupportedDuration));
+            // the existing policy is untouched
+            assert_eq!(stored_policy(&meta_defender, 0).duration, 90);
+        }
+
+        /// Frank buys policy 1 for alice, who holds no tokens.
+        fn create_with_policy_for_alice() -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover_for(accounts.alice, 10_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            meta_defender
         }
 
-        #[ink(message)]
-        pub fn historical_provider_withdraw(&mut self) -> Result<()>{
-            let caller = self.env().caller();
-            match self.historical_provider_map.get(&caller){
-                None => return Err(Error::NotHistoricalUnderwriter),
-                Some(_v) if self.historical_provider_leaving == true => return Err(Error::HistoricalProviderLeavingInProgress),
-                Some(mut v) => {
-                    self.historical_provider_leaving = true;
-
-                    let shadow = self.get_shadow_historical_provider(&v);
-                    
-                    if v.ftoken * self.exchange_rate / 100_000 <= shadow {
-                        return Err(Error::InsufficientSToken);
-                    }else {
-                        match self.erc20.transfer(caller, v.ftoken*self.exchange_rate/100_000 - shadow){
-                            Err(e) if e == Erc20Error::InsufficientAllowance => {
-                                self.historical_provider_leaving = false;
-                                return Err(Error::InsufficientAllowance)
-                            },
-                            Err(e) if e == Erc20Error::InsufficientBalance => {
-                                self.historical_provider_leaving = false;
-                                return Err(Error::InsufficientBalance)
-                            },
-                            Err(_e) => {
-                                self.historical_provider_leaving = false;
-                                return Err(Error::TransferError)
-                            },
-                            Ok(_) => {
-                                self.token_frozen_here -= v.ftoken * self.exchange_rate / 100_000 - shadow;
-                                v.ftoken = shadow * 100_000 / self.exchange_rate;
-                                self.historical_provider_leaving = false;
-                                return Ok(());}
-                        }    
-                        
-                    }
+        #[ink::test]
+        fn buy_cover_for_records_payer_and_beneficiary() {
+            let accounts = default_accounts();
+            let meta_defender = create_with_policy_for_alice();
+
+            let policy = stored_policy(&meta_defender, 1);
+            assert_eq!(policy.beneficiary, accounts.alice);
+            assert_eq!(policy.payer, accounts.frank);
+            // the policy is listed for the beneficiary only
+            assert_eq!(meta_defender.get_user_policy_count(accounts.frank), 1);
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 0);
+            match last_event() {
+                Event::PolicyBought(PolicyBought { beneficiary, payer, policy_id, .. }) => {
+                    assert_eq!(beneficiary, accounts.alice);
+                    assert_eq!(payer, accounts.frank);
+                    assert_eq!(policy_id, 1);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyBought event"),
             }
         }
 
-        #[ink(message)]
-        pub fn try_policy_cancel(&mut self, id: u128) -> Result<()> {
-            match self.policies.get(id) {
-                None => return Err(Error::NotExistedPolicy),
-                Some(v) if v.is_canceled == true =>  return Err(Error::AlreadyCancelledPolicy),
-                Some(mut v) => {
-                    if id == 0 {
-                        match self.execute_cancel(&mut v){
-                            Err(e) => return Err(e),
-                            Ok(_) => return Ok(()),
-                        };
-                        
-                    }else{
-                        match self.policies.get(id -1) {
-                            None => return Err(Error::NotExistedPolicy),
-                            Some(p) if p.is_canceled == false => return Err(Error::PreviousPolicyNotCancelled),
-                            Some(_p) => {
-                                match self.execute_cancel(&mut v){
-                                    Err(e) => return Err(e),
-                                    Ok(_) => return Ok(()),
-                                };
-                            }
-                        }
-                    }
+        #[ink::test]
+        fn cancel_refunds_the_deposit_to_the_payer() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy_for_alice();
+            let deposit = stored_policy(&meta_defender, 1).deposit;
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            let before = mock_erc20::balance_of(accounts.frank);
+
+            // the beneficiary may cancel, the payer is refunded
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.try_policy_cancel(1), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), before + deposit);
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 0);
+            match last_event() {
+                Event::PolicyCancelled(PolicyCancelled { caller, recipient, .. }) => {
+                    assert_eq!(caller, accounts.alice);
+                    assert_eq!(recipient, accounts.frank);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyCancelled event"),
             }
         }
 
-        fn do_policy_cancel(&mut self, policy: &mut PolicyInfo, caller: AccountId) -> Result<()>{
-            self.total_coverage -= policy.coverage;
-            self.acc_sps_down += policy.delta_acc_sps;
-            policy.is_canceled = true;
-            self.latest_unfrozen_index = policy.latest_provider_index;
-            self.update_k_last_by_cancel(self.total_coverage);
+        #[ink::test]
+        fn only_beneficiary_claims_a_cover_bought_for_them() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy_for_alice();
+            mock_erc20::mint(accounts.django, 1_000_000);
 
-            match self.erc20.transfer(caller, policy.deposit){
-                Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                Err(_e) => return Err(Error::TransferError),
-                Ok(_) => return Ok(()),
-            }
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(1, Hash::default(), 10_000), Err(Error::NotBeneficiary));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.policy_claim_apply(1, Hash::default(), 10_000), Ok(()));
 
+            let payer_balance = mock_erc20::balance_of(accounts.frank);
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(1, 10_000), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.alice), 10_000);
+            assert_eq!(mock_erc20::balance_of(accounts.frank), payer_balance);
         }
 
+        #[ink::test]
+        fn buy_cover_respects_max_premium() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let quote = meta_defender.get_quote(10_000, 90).unwrap();
+
+            // the price moved up by one since the user looked at it
+            set_sender(accounts.frank);
+            let balance = mock_erc20::balance_of(accounts.frank);
+            assert_eq!(
+                meta_defender.buy_cover(10_000, 90, quote.cover_fee - 1, Timestamp::MAX),
+                Err(Error::SlippageExceeded)
+            );
+            assert_eq!(
+                meta_defender.buy_cover_for(accounts.alice, 10_000, 90, quote.cover_fee - 1, Timestamp::MAX),
+                Err(Error::SlippageExceeded)
+            );
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance);
 
-        fn update_k_last_by_cancel(&mut self, total_coverage: Balance){
-            if self.token_staked_here > total_coverage {
-                let useable_capital = self.token_staked_here - total_coverage;
-                let tentative_fee = self.k_last / (useable_capital + self.virtual_param);
-                if tentative_fee >= self.min_fee {
-                    ()
-                }else{
-                    self.k_last = self.min_fee * (useable_capital + self.virtual_param);
-                }
-            }else{
-                ()
-            }
+            assert_eq!(meta_defender.buy_cover(10_000, 90, quote.cover_fee, Timestamp::MAX), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance - quote.total);
         }
 
-        fn execute_cancel(&mut self, policy: &mut PolicyInfo) -> Result<()> {
-            let today = self.env().block_timestamp();
-            if policy.effective_until > today {
-                return Err(Error::NotExpiredPolicy);
-            } else if policy.in_claim_applying == true {
-                return Err(Error::ClaimingInProgress);
-            } else{
-                let time_pass = today - policy.effective_until;
-                if time_pass <= 86_400_000 {
-                    let caller = self.env().caller();
-                    if caller != policy.beneficiary {
-                        return Err(Error::OnlyPolicyHolderCanCancel);
-                    }else{
-                        match self.do_policy_cancel(policy, caller){
-                            Err(e) => return Err(e),
-                            Ok(_) => return Ok(()),
-                        }
-                    }
-                } else{
-                        let caller = self.env().caller();
-                        match self.do_policy_cancel(policy, caller){
-                            Err(e) => return Err(e),
-                            Ok(_) => return Ok(()),
-                        }
-                }
-            }
+        #[ink::test]
+        fn buy_cover_respects_deadline() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_block_timestamp(5_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, 4_999), Err(Error::Expired));
+            assert_eq!(
+                meta_defender.buy_cover_for(accounts.alice, 1_000, 90, Balance::MAX, 4_999),
+                Err(Error::Expired)
+            );
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, 5_000), Ok(()));
         }
 
-        #[ink(message)]
-        pub fn policy_claim_apply(&mut self, id: u128) -> Result<()> {
-            let caller = self.env().caller();
-            let today = self.env().block_timestamp();
-            match self.policies.get(id) {
-                None => return Err(Error::NotExistedPolicy),
-                Some(p) if p.beneficiary != caller => return Err(Error::NotBeneficiary),
-                Some(p) if p.is_claimed == true => return Err(Error::AlreadyClaimedPolicy),
-                Some(p) if p.in_claim_applying == true => return Err(Error::InClaimingProgress),
-                Some(p) if p.is_canceled == true => return Err(Error:: AlreadyCancelledPolicy),
-                Some(p) if today > p.effective_until => return Err(Error::NotEffectivePolicy),
-                Some(mut p) => {
-                    p.in_claim_applying = true;
-                    return Ok(());
+        #[ink::test]
+        fn renew_policy_runs_on_from_expiry() {
+            let accounts = default_accounts();
+            set_block_timestamp(1_000);
+            let mut meta_defender = create_with_policy();
+            let old = stored_policy(&meta_defender, 0);
+
+            set_block_timestamp(80 * 86_400_000);
+            let quote = meta_defender.get_quote(10_000, 90).unwrap();
+            let balance = mock_erc20::balance_of(accounts.frank);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(0, 90, quote.cover_fee, Timestamp::MAX), Ok(()));
+
+            // the old deposit is rolled over
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance - (quote.total - old.deposit));
+            let renewed = stored_policy(&meta_defender, 1);
+            assert_eq!(renewed.beneficiary, accounts.frank);
+            assert_eq!(renewed.coverage, 10_000);
+            assert_eq!(renewed.deposit, quote.deposit);
+            assert_eq!(renewed.effective_until, old.effective_until + 90 * 86_400_000);
+            assert!(stored_policy(&meta_defender, 0).is_canceled);
+            match last_event() {
+                Event::PolicyRenewed(PolicyRenewed { policy_id, new_policy_id, cover_fee, deposit, effective_until }) => {
+                    assert_eq!(policy_id, 0);
+                    assert_eq!(new_policy_id, 1);
+                    assert_eq!(cover_fee, quote.cover_fee);
+                    assert_eq!(deposit, quote.deposit);
+                    assert_eq!(effective_until, renewed.effective_until);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyRenewed event"),
             }
+
+            // still covered between the two terms
+            set_block_timestamp(old.effective_until + 1);
+            assert_eq!(meta_defender.policy_claim_apply(1, Hash::default(), 10_000), Ok(()));
         }
-        
-        #[ink(message)]
-        pub fn refuse_apply(&mut self, id: u128) -> Result<()> {
-            let caller = self.env().caller();
-            if caller != self.judger {
-                return Err(Error::NotJudger);
-            }else{
-                match self.policies.get(id) {
-                    None => return Err(Error::NotExistedPolicy),
-                    Some(mut p) => {
-                        p.in_claim_applying = false;
-                        return Ok(());
-                    }
-                }
-            }
+
+        #[ink::test]
+        fn renew_policy_keeps_the_shadow_of_one_coverage() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let old = stored_policy(&meta_defender, 0);
+
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+            let renewed = stored_policy(&meta_defender, 1);
+
+            assert_eq!(meta_defender.total_coverage, 10_000);
+            assert_eq!(meta_defender.junior.acc_sps, old.delta_acc_sps + renewed.delta_acc_sps);
+            assert_eq!(meta_defender.junior.acc_sps_down, old.delta_acc_sps);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(10_000));
+
+            // the renewed policy is next in line and frees the capital once cancelled
+            set_block_timestamp(renewed.effective_until + 1);
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::AlreadyCancelledPolicy));
+            assert_eq!(meta_defender.try_policy_cancel(1), Ok(()));
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(0));
         }
 
-        #[ink(message)]
-        pub fn accept_apply(&mut self, id: u128) -> Result<()> {
-            let caller = self.env().caller();
-            if caller != self.judger {
-                return Err(Error::NotJudger);
-            }else{
-                match self.policies.get(id) {
-                    None => return Err(Error::NotExistedPolicy),
-                    Some(p) if p.in_claim_applying == false => return Err(Error::NotInClaimingProgress),
-                    Some(mut p) => {
-                        if self.erc20.balance_of(self.risk_reserve) >= p.coverage {
-                            match self.erc20.transfer_from(self.risk_reserve, p.beneficiary, p.coverage){
-                                Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                                Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                                Err(_e) => return Err(Error::TransferError),
-                                Ok(_) => {
-                                    p.in_claim_applying = false;
-                                    p.is_claimed = true;
-                                    return Ok(())},
-                            }
-                        } else {
-                            match self.erc20.transfer_from(self.risk_reserve, p.beneficiary, self.erc20.balance_of(self.risk_reserve)){
-                                Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                                Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                                Err(_e) => return Err(Error::TransferError),
-                                Ok(_) => {
-                                    p.in_claim_applying = false;
-                                    p.is_claimed = true;
-                                    let exceeded = p.coverage - self.erc20.balance_of(self.risk_reserve);
-                                    match self.exceeded_pay(p.beneficiary, exceeded){
-                                        Err(e) => return Err(e),
-                                        Ok(_) => return Ok(()),
-                                    }
-                                },
-                            }
-                            
-                            
-                        }
+        #[ink::test]
+        fn renew_policy_releases_the_shadow_of_a_policy_not_first_in_line() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
+            let first = stored_policy(&meta_defender, 0);
+            let second = stored_policy(&meta_defender, 1);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(20_000));
+
+            // policy 1 is renewed while policy 0 still runs ahead of it
+            set_block_timestamp(30 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(1, 30, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 0);
+            assert_eq!(meta_defender.junior.acc_sps_down, second.delta_acc_sps);
+            assert_eq!(stored_policy(&meta_defender, 1).delta_acc_sps, 0);
+            assert_eq!(meta_defender.total_coverage, 20_000);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(20_000));
+
+            // once policy 0 is cancelled, only the renewed policy keeps capital frozen
+            set_block_timestamp(90 * 86_400_000);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 2);
+            assert_eq!(meta_defender.junior.acc_sps_down, first.delta_acc_sps + second.delta_acc_sps);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(10_000));
+        }
 
-                    }
+        #[ink::test]
+        fn renew_policy_refunds_an_oversized_deposit() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_policy_term(7, 1_000), Ok(()));
+
+            let quote = meta_defender.get_quote(10_000, 7).unwrap();
+            assert!(quote.total < 10);
+            let balance = mock_erc20::balance_of(accounts.frank);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(0, 7, Balance::MAX, Timestamp::MAX), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance + 10 - quote.total);
+        }
+
+        #[ink::test]
+        fn renew_policy_checks_the_policy() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            let old = stored_policy(&meta_defender, 0);
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, Timestamp::MAX), Err(Error::NotPayer));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(5, 90, Balance::MAX, Timestamp::MAX), Err(Error::NotExistedPolicy));
+            assert_eq!(meta_defender.renew_policy(0, 45, Balance::MAX, Timestamp::MAX), Err(Error::UnsupportedDuration));
+            assert_eq!(meta_defender.renew_policy(0, 90, 0, Timestamp::MAX), Err(Error::SlippageExceeded));
+
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, Timestamp::MAX), Err(Error::ClaimingInProgress));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Ok(()));
+
+            // the holder's day after expiry is the last chance
+            set_block_timestamp(old.effective_until + 86_400_001);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, Timestamp::MAX), Err(Error::NotEffectivePolicy));
+            assert_eq!(meta_defender.renew_policy(0, 90, Balance::MAX, 0), Err(Error::Expired));
+            assert_eq!(meta_defender.policy_count, 1);
+        }
+
+        /// Adds a 30 days term and has frank buy policy 1 over it, after policy 0 over 90 days.
+        fn create_with_short_policy() -> MetaDefender {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_policy_term(30, 50_000), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 30, Balance::MAX, Timestamp::MAX), Ok(()));
+            meta_defender
+        }
+
+        #[ink::test]
+        fn policies_are_cancelled_out_of_order() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
+            let first = stored_policy(&meta_defender, 0);
+            let second = stored_policy(&meta_defender, 1);
+
+            set_block_timestamp(31 * 86_400_000);
+            let balance = mock_erc20::balance_of(accounts.frank);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(1), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance + second.deposit);
+            assert_eq!(meta_defender.total_coverage, 10_000);
+            // policy 0 still runs, the shadow of policy 1 waits for it
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 0);
+            assert_eq!(meta_defender.junior.acc_sps_down, 0);
+
+            set_block_timestamp(90 * 86_400_000);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 2);
+            assert_eq!(meta_defender.junior.acc_sps_down, first.delta_acc_sps + second.delta_acc_sps);
+            assert_eq!(meta_defender.latest_unfrozen_index, second.latest_provider_index);
+            let eve = meta_defender.provider_map.get(accounts.eve).unwrap();
+            assert_eq!(meta_defender.get_shadow(&eve), Ok(0));
+        }
+
+        #[ink::test]
+        fn cancel_expired_sweeps_stale_policies() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
+            let deposits = stored_policy(&meta_defender, 0).deposit + stored_policy(&meta_defender, 1).deposit;
+            set_block_timestamp(10 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+
+            // within the holder's day nothing can be swept
+            set_block_timestamp(91 * 86_400_000);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.cancel_expired(10), Ok(0));
+
+            // policy 2 was bought later and still runs
+            set_block_timestamp(91 * 86_400_000 + 1);
+            assert_eq!(meta_defender.cancel_expired(10), Ok(2));
+            assert_eq!(mock_erc20::balance_of(accounts.alice), deposits);
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 2);
+            assert!(stored_policy(&meta_defender, 1).is_canceled);
+            assert!(!stored_policy(&meta_defender, 2).is_canceled);
+            assert_eq!(meta_defender.total_coverage, 1_000);
+            match last_event() {
+                Event::PolicyCancelled(PolicyCancelled { policy_id, caller, recipient, deposit, bounty }) => {
+                    assert_eq!(policy_id, 1);
+                    assert_eq!(caller, accounts.alice);
+                    assert_eq!(recipient, accounts.frank);
+                    assert_eq!(bounty, deposit);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyCancelled event"),
             }
         }
 
+        #[ink::test]
+        fn cancel_expired_is_bounded() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
 
-        fn exceeded_pay(&mut self, to: AccountId, exceeded: Balance) -> Result<()> {
-            let pre_reserve = self.token_staked_here + self.token_frozen_here;
-            let after_reserve = pre_reserve - exceeded;
+            set_block_timestamp(92 * 86_400_000);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.cancel_expired(1), Ok(1));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 1);
+            assert_eq!(meta_defender.cancel_expired(0), Ok(0));
+            assert_eq!(meta_defender.cancel_expired(u32::MAX), Ok(1));
+            assert_eq!(meta_defender.cancel_expired(u32::MAX), Ok(0));
+        }
 
-            let delta_rate = after_reserve * 100_000 / pre_reserve;
+        #[ink::test]
+        fn cancel_expired_stops_at_a_claim() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
 
-            self.exchange_rate = self.exchange_rate * delta_rate / 100_000;
+            set_block_timestamp(92 * 86_400_000);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.cancel_expired(10), Ok(0));
+            // policy 1 can still be cancelled on its own
+            assert_eq!(meta_defender.try_policy_cancel(1), Ok(()));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 0);
 
-            self.token_staked_here = self.token_staked_here * delta_rate / 100_000;
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.refuse_apply(0, 0), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.cancel_expired(10), Ok(2));
+        }
 
-            self.token_frozen_here = self.token_frozen_here * delta_rate / 100_000;
+        #[ink::test]
+        fn keeper_share_is_set_by_officials() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            assert_eq!(meta_defender.get_keeper_share(), Rate::ONE);
 
-            match self.erc20.transfer(to, exceeded) {
-                Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                Err(_e) => return Err(Error::TransferError),
-                Ok(_) => return Ok(()),
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.set_keeper_share(10_000), Err(Error::NotOfficial));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_keeper_share(Rate::ONE + 1), Err(Error::InvalidKeeperShare));
+            assert_eq!(meta_defender.set_keeper_share(10_000), Ok(()));
+            assert_eq!(meta_defender.get_keeper_share(), 10_000);
+            match last_event() {
+                Event::KeeperShareSet(KeeperShareSet { share }) => assert_eq!(share, 10_000),
+                _ => panic!("encountered unexpected event kind: expected a KeeperShareSet event"),
             }
         }
 
-        #[ink(message)]
-        pub fn unused_capital_for_mining(&mut self, amount: Balance, to: AccountId) -> Result<()> {
-            let caller = self.env().caller();
-            if caller != self.judger {
-                return Err(Error::NotJudger);
-            }else {
-                match self.is_valid_mining_proxy.get(to) {
-                    None => return Err(Error::NotValidMiningProxy),
-                    Some(v) if v == false => return Err(Error::NotValidMiningProxy),
-                    Some(_v) => {
-                        match self.erc20.transfer(to, amount) {
-                            Err(e) if e == Erc20Error::InsufficientAllowance => return Err(Error::InsufficientAllowance),
-                            Err(e) if e == Erc20Error::InsufficientBalance => return Err(Error::InsufficientBalance),
-                            Err(_e) => return Err(Error::TransferError),
-                            Ok(_) => return Ok(()),
-                        }
-                    }
+        #[ink::test]
+        fn keeper_cancel_batch_splits_deposits() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_short_policy();
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.set_keeper_share(20_000), Ok(()));
+            let first = stored_policy(&meta_defender, 0);
+            let second = stored_policy(&meta_defender, 1);
+
+            // policy 1 ended at day 30, policy 0 still runs
+            set_block_timestamp(32 * 86_400_000);
+            let balance = mock_erc20::balance_of(accounts.frank);
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.keeper_cancel_batch(vec![0, 1, 1, 7]), Ok(1));
+            let bounty = second.deposit / 5;
+            assert_eq!(mock_erc20::balance_of(accounts.alice), bounty);
+            assert_eq!(mock_erc20::balance_of(accounts.frank), balance + second.deposit - bounty);
+            assert_eq!(meta_defender.total_coverage, first.coverage);
+            match last_event() {
+                Event::PolicyCancelled(PolicyCancelled { policy_id, caller, recipient, bounty: paid, .. }) => {
+                    assert_eq!(policy_id, 1);
+                    assert_eq!(caller, accounts.alice);
+                    assert_eq!(recipient, accounts.frank);
+                    assert_eq!(paid, bounty);
                 }
+                _ => panic!("encountered unexpected event kind: expected a PolicyCancelled event"),
             }
-            
+
+            // policy 0 is folded in as soon as it is cancelled, along with policy 1
+            set_block_timestamp(92 * 86_400_000);
+            assert_eq!(meta_defender.keeper_cancel_batch(vec![0]), Ok(1));
+            assert_eq!(meta_defender.get_unfrozen_policy_count(), 2);
+            assert_eq!(meta_defender.total_coverage, 0);
         }
 
+        #[ink::test]
+        fn keeper_cancel_batch_is_bounded() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            set_sender(accounts.alice);
+            let ids = (0..=MAX_SWEEP as u128).collect::<Vec<_>>();
+            assert_eq!(meta_defender.keeper_cancel_batch(ids), Err(Error::BatchTooLarge));
+            assert_eq!(meta_defender.keeper_cancel_batch(Vec::new()), Ok(0));
+        }
 
-        #[ink(message)]
-        pub fn check_judger(&self) -> AccountId{
-            self.judger
+        #[ink::test]
+        fn locked_messages_are_rejected() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.is_valid_mining_proxy.insert(accounts.alice, &true);
+            meta_defender.locked = true;
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000, Tranche::Junior), Err(Error::ReentrantCall));
+            assert_eq!(meta_defender.provider_take_reward(), Err(Error::ReentrantCall));
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::ReentrantCall));
+            assert_eq!(meta_defender.historical_provider_withdraw(), Err(Error::ReentrantCall));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::ReentrantCall));
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::ReentrantCall));
+            set_sender(accounts.charlie);
+            assert_eq!(meta_defender.accept_apply(0, 10_000), Err(Error::ReentrantCall));
+            assert_eq!(meta_defender.unused_capital_for_mining(1, accounts.alice), Err(Error::ReentrantCall));
+            set_sender(accounts.bob);
+            assert_eq!(meta_defender.team_claim(), Err(Error::ReentrantCall));
+
+            // messages without token calls are not guarded
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.policy_claim_apply(0, Hash::default(), 10_000), Ok(()));
         }
 
-        #[ink(message)]
-        pub fn check_official(&self) -> AccountId{
-            self.official
+        #[ink::test]
+        fn lock_is_released_on_errors() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.alice);
+            assert_eq!(meta_defender.buy_cover(1_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::InsufficientBalance));
+            assert!(!meta_defender.locked);
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::NotUnderwriter));
+            assert!(!meta_defender.locked);
+            assert_eq!(meta_defender.team_claim(), Err(Error::NotOfficial));
+            assert!(!meta_defender.locked);
+            assert_eq!(meta_defender.try_policy_cancel(0), Err(Error::NotExpiredPolicy));
+            assert!(!meta_defender.locked);
+
+            // the token refuses to pay
+            set_sender(accounts.eve);
+            let this = contract_id();
+            mock_erc20::transfer(this, accounts.alice, mock_erc20::balance_of(this)).unwrap();
+            assert_eq!(meta_defender.provider_take_reward(), Err(Error::InsufficientBalance));
+            assert!(!meta_defender.locked);
         }
 
+        #[ink::test]
+        fn historical_provider_is_not_locked_out() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
 
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+            // everything left is frozen by the active policy
+            assert_eq!(meta_defender.historical_provider_withdraw(), Err(Error::InsufficientSToken));
+            assert!(!meta_defender.locked);
 
-        // #[ink(message)]
-        // pub fn get(&self) ->  Balance{
-        //     self.erc20.total_supply()        
-        // }
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
 
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.historical_provider_withdraw(), Ok(()));
+            assert!(!meta_defender.locked);
+        }
 
-        // #[ink(message)]
-        // pub fn balance_of(&self, owner: AccountId) -> Balance {
-        //     self.erc20.balance_of(owner)
-        // }
+        #[ink::test]
+        fn checked_helpers_work() {
+            assert_eq!(add(u128::MAX, 1), Err(Error::ArithmeticOverflow));
+            assert_eq!(sub(1, 2), Err(Error::ArithmeticOverflow));
+            assert_eq!(mul(u128::MAX, 2), Err(Error::ArithmeticOverflow));
+            assert_eq!(div(1, 0), Err(Error::DivisionByZero));
+            assert_eq!(mul_div(10, 3, 4), Ok(7));
+            assert_eq!(mul_div(10, 3, 0), Err(Error::DivisionByZero));
+        }
 
-        // #[ink(message)]
-        // pub fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
-        //     self.erc20.allowance(owner, spender)
-        // }
+        #[ink::test]
+        fn buy_cover_rounds_in_favor_of_the_pool() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_001, 90, Balance::MAX, Timestamp::MAX), Ok(()));
+
+            // 10_001 * 2% = 200.02 and 201 * 5% = 10.05 are both rounded up
+            let policy = meta_defender.policies.get(0).unwrap();
+            assert_eq!(policy.deposit, 11);
+            assert_eq!(mock_erc20::balance_of(accounts.frank), 1_000_000 - 201 - 11);
+
+            // the underwriters get at most the 191 of the premium left after the team reward
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Ok(()));
+            assert_eq!(mock_erc20::balance_of(accounts.eve), 191);
+        }
 
+        #[ink::test]
+        fn buy_cover_without_stoken_supply_fails() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_funded();
 
-        // fn transfer(&mut self, to: AccountId, amount: Balance) {
-        //     self.erc20.transfer(to, amount);
-        // }
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provide_capital(1_000_000, Tranche::Junior), Ok(()));
+            meta_defender.junior.stoken_supply = 0;
 
-        // #[ink(message)]
-        // pub fn approve(&mut self, 
-        //     spender: AccountId, value: Balance){
-        //     self.erc20.approve(spender, value);   
-        // }
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::DivisionByZero));
+            assert_eq!(mock_erc20::balance_of(accounts.frank), 1_000_000);
+        }
 
-        // pub fn transfer_from(&mut self, from: AccountId, to: AccountId, value: Balance ) {
-        //     self.transfer_from(from, to, value);
-        // }
+        #[ink::test]
+        fn buy_cover_overflow_fails() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.junior.acc_sps = u128::MAX;
 
-    }
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.buy_cover(10_000, 90, Balance::MAX, Timestamp::MAX), Err(Error::ArithmeticOverflow));
+        }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use ink::codegen::Env;
+        #[ink::test]
+        fn provider_take_reward_overflow_fails() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+            meta_defender.junior.acc_rps = u128::MAX;
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.provider_take_reward(), Err(Error::ArithmeticOverflow));
+            assert_eq!(meta_defender.provider_abolish(), Err(Error::ArithmeticOverflow));
+            assert_eq!(meta_defender.get_unfrozen_capital(), 990_000);
+        }
+
+        #[ink::test]
+        fn historical_provider_shadow_is_in_tokens() {
+            let accounts = default_accounts();
+            let mut meta_defender = create_with_policy();
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.get_unfrozen_capital(), 990_000);
+            assert_eq!(meta_defender.provider_abolish(), Ok(()));
+
+            // the frozen 10_000 back exactly the coverage of the active policy
+            assert_eq!(meta_defender.get_unfrozen_capital(), 0);
+
+            set_block_timestamp(90 * 86_400_000);
+            set_sender(accounts.frank);
+            assert_eq!(meta_defender.try_policy_cancel(0), Ok(()));
+
+            set_sender(accounts.eve);
+            assert_eq!(meta_defender.get_unfrozen_capital(), 10_000);
+        }
 
         #[ink::test]
         fn judger_transfer_should_works() {
@@ -880,10 +6674,13 @@ mod meta_defender {
             let charlie  = accounts.charlie;
 
             // current judger is charlie, 
-            // charlie initiates the judger transfer, should work
+            // charlie initiates the judger transfer, alice accepts, should work
             set_sender(charlie);
-            meta_defender.judger_transfer(alice);
-            assert_eq!(meta_defender.check_judger(), alice);
+            meta_defender.propose_judger(alice, None);
+            set_sender(alice);
+            meta_defender.accept_judger();
+            assert!(meta_defender.has_role(Role::Judger, alice));
+            assert!(!meta_defender.has_role(Role::Judger, charlie));
 
         }
 
@@ -903,8 +6700,10 @@ mod meta_defender {
             set_sender(bob);
             // current judger is charlie, 
             // bob initiates the judger transfer, should not work
-            meta_defender.judger_transfer(alice);
-            assert_eq!(meta_defender.check_judger(), alice);
+            meta_defender.propose_judger(alice, None);
+            set_sender(alice);
+            meta_defender.accept_judger();
+            assert!(meta_defender.has_role(Role::Judger, alice));
         }
 
         // #[ink::test]
@@ -990,57 +6789,167 @@ mod meta_defender {
             .expect("Cannot get account balance")
         }
 
+        /// For calculating the event topic hash.
+        struct PrefixedValue<'a, 'b, T> {
+            pub prefix: &'a [u8],
+            pub value: &'b T,
+        }
+
+        impl<X> scale::Encode for PrefixedValue<'_, '_, X>
+        where
+            X: scale::Encode,
+        {
+            #[inline]
+            fn size_hint(&self) -> usize {
+                self.prefix.size_hint() + self.value.size_hint()
+            }
+
+            #[inline]
+            fn encode_to<T: scale::Output + ?Sized>(&self, dest: &mut T) {
+                self.prefix.encode_to(dest);
+                self.value.encode_to(dest);
+            }
+        }
+
+        fn encoded_into_hash<T>(entity: &T) -> Hash
+        where
+            T: scale::Encode,
+        {
+            use ink::env::hash::{
+                Blake2x256,
+                CryptoHash,
+                HashOutput,
+            };
+
+            let mut result = Hash::from([0x00; 32]);
+            let len_result = result.as_ref().len();
+            let encoded = entity.encode();
+            let len_encoded = encoded.len();
+            if len_encoded <= len_result {
+                result.as_mut()[..len_encoded].copy_from_slice(&encoded);
+                return result
+            }
+            let mut hash_output =
+                <<Blake2x256 as HashOutput>::Type as Default>::default();
+            <Blake2x256 as CryptoHash>::hash(&encoded, &mut hash_output);
+            let copy_len = core::cmp::min(hash_output.len(), len_result);
+            result.as_mut()[0..copy_len].copy_from_slice(&hash_output[0..copy_len]);
+            result
+        }
+
     }
 
     #[cfg(all(test, feature = "e2e-tests"))]
     mod e2e_tests {
-        use super::MetaDefenderRef;
+        use super::*;
+        use erc20::Erc20Ref;
         use ink_e2e::build_message;
 
         type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-    
+
         #[ink_e2e::test(
             additional_contracts = "erc20/Cargo.toml"
         )]
         async fn e2e_delegator(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
 
-            let erc20_constructor = Erc20Ref::new(
-                10_000
-            );
-
+            let erc20_constructor = Erc20Ref::new(1_000_000);
             let erc20_acc_id = client
-            .instantiate("erc20", &mut ink_e2e::alice(), erc20_constructor, 0, None)
-            .await
-            .expect("instantiate failed")
-            .account_id;
-
-
-            let md_constructor = MetaDefenderRef::new(
-                ink_e2e::alice(),
-                ink_e2e::alice(),
-                ink_e2e::alice(),
-                100_000,
-                erc20_acc_id
-            );
+                .instantiate("erc20", &ink_e2e::alice(), erc20_constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
 
+            // alice is official and judger
+            let md_constructor = MetaDefenderRef::new(alice, alice, bob, 100_000, erc20_acc_id);
             let md_acc_id = client
-                .instantiate("meta_defender", &mut ink_e2e::alice(), md_constructor, 0, None)
+                .instantiate("meta_defender", &ink_e2e::alice(), md_constructor, 0, None)
                 .await
                 .expect("instantiate failed")
                 .account_id;
 
-            let transfer = build_message::<Erc20Ref>(erc20_acc_id.clone())
-                .call(|contract| contract.transfer(ink_e2e::bob(), 2000));
+            let approve = build_message::<Erc20Ref>(erc20_acc_id.clone())
+                .call(|contract| contract.approve(md_acc_id, 2_000));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+
+            let provide = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.provide_capital(2_000, Tranche::Junior));
+            client.call(&ink_e2e::alice(), provide, 0, None).await.expect("provide_capital failed");
+
+            let useable = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.get_useable_capital());
+            let useable = client.call_dry_run(&ink_e2e::alice(), &useable, 0, None).await.return_value();
+            assert_eq!(useable, 2_000);
+            Ok(())
+        }
 
+        #[ink_e2e::test(
+            additional_contracts = "erc20/Cargo.toml"
+        )]
+        async fn upgrade_keeps_storage(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
 
+            let erc20_constructor = Erc20Ref::new(1_000_000);
             let erc20_acc_id = client
-                .instantiate("delegator", &mut ink_e2e::alice(), constructor, 0, None)
+                .instantiate("erc20", &ink_e2e::alice(), erc20_constructor, 0, None)
                 .await
                 .expect("instantiate failed")
                 .account_id;
+
+            // alice is official and judger
+            let md_constructor = MetaDefenderRef::new(alice, alice, bob, 100_000, erc20_acc_id);
+            let md_acc_id = client
+                .instantiate("meta_defender", &ink_e2e::alice(), md_constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+
+            let approve = build_message::<Erc20Ref>(erc20_acc_id.clone())
+                .call(|contract| contract.approve(md_acc_id, 100_000));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            let provide = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.provide_capital(100_000, Tranche::Junior));
+            client.call(&ink_e2e::alice(), provide, 0, None).await.expect("provide_capital failed");
+
+            // the current code, i.e. an upgrade from the current layout, the one from version 1
+            // is `upgrade_from_v1` of the migration contract
+            let uploaded = client
+                .upload("meta_defender", &ink_e2e::alice(), None)
+                .await
+                .expect("upload failed");
+            let mut code_hash = [0u8; 32];
+            code_hash.copy_from_slice(uploaded.code_hash.as_ref());
+
+            let upgrade = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.upgrade(code_hash));
+            let rejected = client.call_dry_run(&ink_e2e::bob(), &upgrade, 0, None).await.return_value();
+            assert_eq!(rejected, Err(Error::NotOfficial));
+            client.call(&ink_e2e::alice(), upgrade, 0, None).await.expect("upgrade failed");
+
+            let migrate = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.migrate(vec![alice], Vec::new()));
+            client.call(&ink_e2e::alice(), migrate, 0, None).await.expect("migrate failed");
+            let finish = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.finish_migration());
+            client.call(&ink_e2e::alice(), finish, 0, None).await.expect("finish_migration failed");
+
+            let version = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.get_storage_version());
+            let version = client.call_dry_run(&ink_e2e::alice(), &version, 0, None).await.return_value();
+            assert_eq!(version, STORAGE_VERSION);
+
+            let useable = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.get_useable_capital());
+            let useable = client.call_dry_run(&ink_e2e::alice(), &useable, 0, None).await.return_value();
+            assert_eq!(useable, 100_000);
+            let unfrozen = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.get_unfrozen_capital());
+            let unfrozen = client.call_dry_run(&ink_e2e::alice(), &unfrozen, 0, None).await.return_value();
+            assert_eq!(unfrozen, 100_000);
             Ok(())
         }
-    
     }
 }
     
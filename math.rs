@@ -0,0 +1,132 @@
+//! Fixed-point arithmetic of the Meta_Defender accounting.
+//!
+//! The contract keeps two precisions: the per-stoken accumulators `acc_rps` and
+//! `acc_sps` are scaled by `Ray::ONE`, while `exchange_rate` and the premium rate
+//! are scaled by `Rate::ONE`. Amounts paid out to users are rounded down, amounts
+//! kept by the pool (premiums, frozen shadow) are rounded up.
+
+/// The rounding direction of a fixed-point operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards zero, used for everything a user receives.
+    Down,
+    /// Away from zero, used for everything the pool keeps or reserves.
+    Up,
+}
+
+/// The fixed-point error types.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MathError {
+    /// Returned if a result does not fit into a `u128`.
+    Overflow,
+    /// Returned if a divisor is zero.
+    DivisionByZero,
+}
+
+/// Returns `a * b / c` rounded in the given direction.
+pub fn mul_div(a: u128, b: u128, c: u128, rounding: Rounding) -> Result<u128, MathError> {
+    if c == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+    let product = a.checked_mul(b).ok_or(MathError::Overflow)?;
+    let quotient = product / c;
+    match rounding {
+        Rounding::Up if product % c != 0 => Ok(quotient + 1),
+        _ => Ok(quotient),
+    }
+}
+
+/// A per-stoken value scaled by `Ray::ONE`, as stored in `acc_rps` and `acc_sps`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ray(pub u128);
+
+impl Ray {
+    /// The scale of a `Ray`.
+    pub const ONE: u128 = 10_000_000_000_000;
+
+    /// Returns `amount` spread over `shares`.
+    pub fn per_share(amount: u128, shares: u128, rounding: Rounding) -> Result<Self, MathError> {
+        mul_div(amount, Self::ONE, shares, rounding).map(Ray)
+    }
+
+    /// Returns the amount accumulated by `shares` at this per-share value.
+    pub fn of(self, shares: u128, rounding: Rounding) -> Result<u128, MathError> {
+        mul_div(shares, self.0, Self::ONE, rounding)
+    }
+}
+
+/// A ratio scaled by `Rate::ONE`, as stored in `exchange_rate` and returned by `get_fee`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate(pub u128);
+
+impl Rate {
+    /// The scale of a `Rate`, i.e. a rate of 1.
+    pub const ONE: u128 = 100_000;
+
+    /// Returns the rate `numerator / denominator`.
+    pub fn from_ratio(numerator: u128, denominator: u128, rounding: Rounding) -> Result<Self, MathError> {
+        mul_div(numerator, Self::ONE, denominator, rounding).map(Rate)
+    }
+
+    /// Returns `amount` multiplied by this rate, e.g. the token value of a stoken amount.
+    pub fn mul(self, amount: u128, rounding: Rounding) -> Result<u128, MathError> {
+        mul_div(amount, self.0, Self::ONE, rounding)
+    }
+
+    /// Returns `amount` divided by this rate, e.g. the stoken amount of a token value.
+    pub fn div(self, amount: u128, rounding: Rounding) -> Result<u128, MathError> {
+        mul_div(amount, Self::ONE, self.0, rounding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounds_in_both_directions() {
+        assert_eq!(mul_div(10, 3, 4, Rounding::Down), Ok(7));
+        assert_eq!(mul_div(10, 3, 4, Rounding::Up), Ok(8));
+        // exact results are never rounded
+        assert_eq!(mul_div(10, 4, 4, Rounding::Down), Ok(10));
+        assert_eq!(mul_div(10, 4, 4, Rounding::Up), Ok(10));
+        assert_eq!(mul_div(0, 4, 3, Rounding::Up), Ok(0));
+    }
+
+    #[test]
+    fn mul_div_fails_on_overflow_and_zero_divisor() {
+        assert_eq!(mul_div(u128::MAX, 2, 2, Rounding::Down), Err(MathError::Overflow));
+        assert_eq!(mul_div(1, 1, 0, Rounding::Up), Err(MathError::DivisionByZero));
+    }
+
+    #[test]
+    fn ray_per_share_round_trips() {
+        let acc = Ray::per_share(190, 3, Rounding::Down).unwrap();
+        assert_eq!(acc, Ray(633_333_333_333_333));
+        // the shares never get more than the distributed amount
+        assert_eq!(acc.of(3, Rounding::Down), Ok(189));
+
+        let acc = Ray::per_share(190, 3, Rounding::Up).unwrap();
+        assert_eq!(acc, Ray(633_333_333_333_334));
+        // the reservation never covers less than the reserved amount
+        assert_eq!(acc.of(3, Rounding::Up), Ok(191));
+    }
+
+    #[test]
+    fn ray_per_share_needs_shares() {
+        assert_eq!(Ray::per_share(1, 0, Rounding::Down), Err(MathError::DivisionByZero));
+    }
+
+    #[test]
+    fn rate_converts_between_tokens_and_stokens() {
+        let rate = Rate::from_ratio(2, 3, Rounding::Down).unwrap();
+        assert_eq!(rate, Rate(66_666));
+        assert_eq!(rate.mul(1_000, Rounding::Down), Ok(666));
+        assert_eq!(rate.mul(1_000, Rounding::Up), Ok(667));
+        assert_eq!(rate.div(1_000, Rounding::Down), Ok(1_500));
+        assert_eq!(rate.div(1_000, Rounding::Up), Ok(1_501));
+
+        assert_eq!(Rate(Rate::ONE).mul(1_234, Rounding::Down), Ok(1_234));
+        assert_eq!(Rate(0).div(1, Rounding::Down), Err(MathError::DivisionByZero));
+    }
+}
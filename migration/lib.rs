@@ -0,0 +1,195 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use self::migration::MetaDefenderRef as MigrationRef;
+
+/// The code a meta_defender contract of storage version 1 goes through to reach the current one.
+///
+/// ink! decodes the whole root of a contract before any of its messages run, and the current code
+/// can't decode the root written by version 1. The officials `upgrade` to this code, whose root is
+/// the one of version 1, and call `migrate` with the code hash of the current meta_defender: the
+/// root is rewritten and the code is switched again. Its `migrate` and `finish_migration` then
+/// translate the records.
+#[ink::contract]
+mod migration {
+    use erc20::Erc20Ref;
+    use ink::env::call::FromAccountId;
+    use ink::storage::Mapping;
+    use meta_defender::{MetaDefenderError, Role};
+
+    /// The root of meta_defender at storage version 1, named after it so that the mappings
+    /// have the same keys. Only the mappings this code touches are listed.
+    #[ink(storage)]
+    pub struct MetaDefender {
+        provider_count: u128,
+        exchange_rate: Balance,
+        policy_count: u128,
+        acc_rps: Balance,
+        acc_sps: Balance,
+        acc_sps_down: Balance,
+        token_staked_here: Balance,
+        stoken_supply: Balance,
+        token_frozen_here: Balance,
+        total_coverage: Balance,
+        k_last: u128,
+        latest_unfrozen_index: u128,
+
+        initial_fee: u128,
+        min_fee: u128,
+
+        roles: Mapping<(Role, AccountId), bool>,
+        role_member_count: Mapping<Role, u32>,
+
+        claimable_team_reward: Balance,
+        virtual_param: Balance,
+        locked: bool,
+        storage_version: u32,
+
+        erc20: Erc20Ref,
+        risk_reserve: AccountId,
+    }
+
+    impl MetaDefender {
+        /// Instantiates a contract of storage version 1 as its constructor did, which only the
+        /// upgrade tests need: live contracts reach this code through `upgrade`.
+        #[ink(constructor)]
+        pub fn new(
+            official: AccountId,
+            judger: AccountId,
+            risk_reserve: AccountId,
+            virtual_param: Balance,
+            erc20: AccountId) -> Self {
+            let mut meta_defender = MetaDefender {
+                provider_count: 0,
+                // `Rate::ONE`
+                exchange_rate: 100_000,
+                policy_count: 0,
+                acc_rps: 0,
+                acc_sps: 0,
+                acc_sps_down: 0,
+                token_staked_here: 0,
+                stoken_supply: 0,
+                token_frozen_here: 0,
+                total_coverage: 0,
+                k_last: 0,
+                latest_unfrozen_index: 0,
+                initial_fee: 2000,
+                min_fee: 2000,
+                roles: Default::default(),
+                role_member_count: Default::default(),
+                claimable_team_reward: 0,
+                virtual_param,
+                locked: false,
+                storage_version: 1,
+                erc20: Erc20Ref::from_account_id(erc20),
+                risk_reserve,
+            };
+            for (role, account) in [
+                (Role::Official, official),
+                (Role::PauseAdmin, official),
+                (Role::ProxyManager, official),
+                (Role::Judger, judger),
+            ] {
+                let count = meta_defender.role_member_count.get(role).unwrap_or(0);
+                meta_defender.roles.insert((role, account), &true);
+                meta_defender.role_member_count.insert(role, &(count + 1));
+            }
+            meta_defender
+        }
+
+        /// This message rewrites the root in the layout of the current meta_defender and switches
+        /// to its code `code_hash`
+        ///
+        /// Only an official can call this message, if not, return NotOfficial Error.
+        #[ink(message)]
+        pub fn migrate(&self, code_hash: [u8; 32]) -> Result<(), MetaDefenderError> {
+            // `&self` keeps ink! from writing the root of version 1 back once this returns
+            meta_defender::MetaDefender::migrate_root(self.env().caller())?;
+            ink::env::set_code_hash(&code_hash).map_err(|_| MetaDefenderError::UpgradeFailed)
+        }
+    }
+
+    #[cfg(all(test, feature = "e2e-tests"))]
+    mod e2e_tests {
+        use super::*;
+        use ink_e2e::build_message;
+        use meta_defender::{MetaDefenderRef as UpgradedRef, Tranche, STORAGE_VERSION};
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        #[ink_e2e::test(
+            additional_contracts = "../erc20/Cargo.toml ../Cargo.toml"
+        )]
+        async fn upgrade_from_v1(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(ink_e2e::AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(ink_e2e::AccountKeyring::Bob);
+
+            let erc20_constructor = Erc20Ref::new(1_000_000);
+            let erc20_acc_id = client
+                .instantiate("erc20", &ink_e2e::alice(), erc20_constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+
+            // a contract of version 1, alice is official and judger
+            let v1_constructor = MetaDefenderRef::new(alice, alice, bob, 100_000, erc20_acc_id);
+            let md_acc_id = client
+                .instantiate("meta_defender_migration", &ink_e2e::alice(), v1_constructor, 0, None)
+                .await
+                .expect("instantiate failed")
+                .account_id;
+
+            let uploaded = client
+                .upload("meta_defender", &ink_e2e::alice(), None)
+                .await
+                .expect("upload failed");
+            let mut code_hash = [0u8; 32];
+            code_hash.copy_from_slice(uploaded.code_hash.as_ref());
+
+            let migrate_root = build_message::<MetaDefenderRef>(md_acc_id.clone())
+                .call(|contract| contract.migrate(code_hash));
+            let rejected = client.call_dry_run(&ink_e2e::bob(), &migrate_root, 0, None).await.return_value();
+            assert_eq!(rejected, Err(MetaDefenderError::NotOfficial));
+            client.call(&ink_e2e::alice(), migrate_root, 0, None).await.expect("migrate failed");
+
+            // the current code runs on the rewritten root, its records are still to translate
+            let version = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.get_storage_version());
+            let version = client.call_dry_run(&ink_e2e::alice(), &version, 0, None).await.return_value();
+            assert_eq!(version, 1);
+
+            let migrate = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.migrate(vec![alice], Vec::new()));
+            client.call(&ink_e2e::alice(), migrate, 0, None).await.expect("migrate failed");
+            let finish = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.finish_migration());
+            client.call(&ink_e2e::alice(), finish, 0, None).await.expect("finish_migration failed");
+
+            let version = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.get_storage_version());
+            let version = client.call_dry_run(&ink_e2e::alice(), &version, 0, None).await.return_value();
+            assert_eq!(version, STORAGE_VERSION);
+
+            // the token, the roles and the capital globals were carried over
+            let approve = build_message::<Erc20Ref>(erc20_acc_id.clone())
+                .call(|contract| contract.approve(md_acc_id, 100_000));
+            client.call(&ink_e2e::alice(), approve, 0, None).await.expect("approve failed");
+            let provide = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.provide_capital(100_000, Tranche::Junior));
+            client.call(&ink_e2e::alice(), provide, 0, None).await.expect("provide_capital failed");
+
+            let useable = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.get_useable_capital());
+            let useable = client.call_dry_run(&ink_e2e::alice(), &useable, 0, None).await.return_value();
+            assert_eq!(useable, 100_000);
+            let rate = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.get_exchange_rate(Tranche::Junior));
+            let rate = client.call_dry_run(&ink_e2e::alice(), &rate, 0, None).await.return_value();
+            assert_eq!(rate, 100_000);
+            let quote = build_message::<UpgradedRef>(md_acc_id.clone())
+                .call(|contract| contract.get_quote(1_000, 90));
+            let quote = client.call_dry_run(&ink_e2e::alice(), &quote, 0, None).await.return_value();
+            assert!(quote.is_ok());
+            Ok(())
+        }
+    }
+}
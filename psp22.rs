@@ -0,0 +1,64 @@
+//! The PSP22 interface of the stoken, the share of an underwriter in the pool.
+//!
+//! The trait is named `PSP22` so that ink derives the standard selectors
+//! (`PSP22::transfer`, ...) and wallets and other contracts can call the
+//! stoken like any other PSP22 token.
+
+use ink::prelude::{string::String, vec::Vec};
+use ink::primitives::AccountId;
+
+type Balance = u128;
+
+/// The PSP22 error types.
+#[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PSP22Error {
+    /// Returned for every failure not covered below, carrying the contract error.
+    Custom(String),
+    /// Returned if the owner holds less stoken than transferred.
+    InsufficientBalance,
+    /// Returned if the spender is allowed less than transferred.
+    InsufficientAllowance,
+    ZeroRecipientAddress,
+    ZeroSenderAddress,
+    SafeTransferCheckFailed(String),
+}
+
+#[ink::trait_definition]
+pub trait PSP22 {
+    /// Returns the stoken supply.
+    #[ink(message)]
+    fn total_supply(&self) -> Balance;
+
+    /// Returns the stoken of `owner`.
+    #[ink(message)]
+    fn balance_of(&self, owner: AccountId) -> Balance;
+
+    /// Returns how much stoken `spender` may still transfer on behalf of `owner`.
+    #[ink(message)]
+    fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance;
+
+    /// Transfers `value` stoken from the caller to `to`, along with their share of the position.
+    #[ink(message)]
+    fn transfer(&mut self, to: AccountId, value: Balance, data: Vec<u8>) -> Result<(), PSP22Error>;
+
+    /// Transfers `value` stoken from `from` to `to` out of the caller's allowance.
+    #[ink(message)]
+    fn transfer_from(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        value: Balance,
+        data: Vec<u8>,
+    ) -> Result<(), PSP22Error>;
+
+    /// Allows `spender` to transfer up to `value` stoken of the caller.
+    #[ink(message)]
+    fn approve(&mut self, spender: AccountId, value: Balance) -> Result<(), PSP22Error>;
+
+    #[ink(message)]
+    fn increase_allowance(&mut self, spender: AccountId, delta_value: Balance) -> Result<(), PSP22Error>;
+
+    #[ink(message)]
+    fn decrease_allowance(&mut self, spender: AccountId, delta_value: Balance) -> Result<(), PSP22Error>;
+}